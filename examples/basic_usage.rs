@@ -1,5 +1,4 @@
-use jockey_cli::{cli::{Config, Commands}, process};
-use env_logger;
+use jockey_cli::{cli::{Config, Commands, GenerateArgs}, process};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -8,16 +7,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Create configuration programmatically
     let config = Config {
-        command: Commands::Generate {
-            path: None,
+        command: Commands::Generate(Box::new(GenerateArgs {
             md: false,
             json: true,
-            txt: false,
-            yaml: false,
             exclude: Some("target,node_modules".to_string()),
             parallel: true,
             verbose: true,
-        },
+            ..Default::default()
+        })),
     };
     
     // Process the repository
@@ -0,0 +1,126 @@
+use crate::encoding;
+use crate::error::{Result, JockeyError};
+use crate::output::parse_image;
+use similar::TextDiff;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use colored::*;
+
+struct Change {
+    path: PathBuf,
+    /// New file contents, already encoded like the original file
+    bytes: Vec<u8>,
+    diff: String,
+}
+
+/// Writes the files of an edited image back under `root`, after previewing the changes.
+pub async fn apply(image: &Path, root: &Path, yes: bool) -> Result<()> {
+    let input = fs::read_to_string(image).await.map_err(|e| {
+        JockeyError::Processing(format!("Failed to read image '{}': {}", image.display(), e))
+    })?;
+    let repo = parse_image(&input)?;
+    let paths = repo.relative_paths()?;
+
+    let mut changes = Vec::new();
+    for (path, file) in paths.into_iter().zip(repo.files) {
+        // Deleting files is left to the user; the image only records that it happened
+        if file.deleted {
+            println!("{} {} (deleted in the image)", "Skipped:".blue(), path.display().to_string().yellow());
+            continue;
+        }
+        // Outlines would overwrite the real file with a fraction of it
+        if !file.is_complete() {
+            println!("{} {} (image holds only part of the file)", "Skipped:".blue(), path.display().to_string().yellow());
+            continue;
+        }
+        let destination = root.join(&path);
+        let current = match fs::read(&destination).await {
+            // Compare in the file's own encoding; anything undecodable shows up as a full rewrite
+            Ok(bytes) => Some(
+                encoding::decode(&bytes, file.encoding.as_deref())
+                    .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).into_owned()),
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(JockeyError::Processing(format!(
+                    "Failed to read '{}': {}",
+                    destination.display(),
+                    e
+                )))
+            }
+        };
+        if current.as_deref() == Some(file.content.as_str()) {
+            continue;
+        }
+
+        let display = path.to_string_lossy();
+        let old_header = if current.is_some() { format!("a/{}", display) } else { "/dev/null".to_string() };
+        let diff = TextDiff::from_lines(current.as_deref().unwrap_or(""), &file.content)
+            .unified_diff()
+            .context_radius(3)
+            .header(&old_header, &format!("b/{}", display))
+            .to_string();
+        let bytes = encoding::encode(&file.content, file.encoding.as_deref()).map_err(|e| {
+            JockeyError::Processing(format!("Failed to encode '{}': {}", path.display(), e))
+        })?;
+        changes.push(Change { path, bytes, diff });
+    }
+
+    if changes.is_empty() {
+        println!("\n{}", "No changes to apply.".green().bold());
+        return Ok(());
+    }
+
+    for change in &changes {
+        print_diff(&change.diff);
+    }
+
+    if !yes && !confirm(&format!("Apply changes to {} file(s)?", changes.len()))? {
+        println!("{}", "Aborted, nothing was written.".yellow());
+        return Ok(());
+    }
+
+    for change in &changes {
+        let destination = root.join(&change.path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await.map_err(|e| {
+                JockeyError::Processing(format!("Failed to create directory '{}': {}", parent.display(), e))
+            })?;
+        }
+        fs::write(&destination, &change.bytes).await.map_err(|e| {
+            JockeyError::Processing(format!("Failed to write '{}': {}", destination.display(), e))
+        })?;
+    }
+
+    println!("\n{}", "Jockey image applied successfully!".green().bold());
+    for change in &changes {
+        println!("{} {}", "Updated:".blue(), change.path.display().to_string().yellow());
+    }
+
+    Ok(())
+}
+
+fn print_diff(diff: &str) {
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,139 @@
+use crate::error::{Result, JockeyError};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub const ATTRIBUTES_FILENAME: &str = ".gitattributes";
+
+/// A `.gitattributes` marking that keeps a file out of images
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Marker {
+    Generated,
+    Vendored,
+    NoDiff,
+}
+
+impl Marker {
+    const ALL: [Marker; 3] = [Marker::Generated, Marker::Vendored, Marker::NoDiff];
+
+    /// Whether an attribute token of a `.gitattributes` line sets (`Some(true)`) or unsets
+    /// (`Some(false)`) this marker.
+    fn parse(self, token: &str) -> Option<bool> {
+        let name = match self {
+            Marker::Generated => "linguist-generated",
+            Marker::Vendored => "linguist-vendored",
+            Marker::NoDiff => {
+                // `binary` is the built-in macro for `-diff -merge -text`; a diff driver
+                // (`diff=<driver>`) still produces text diffs
+                return match token {
+                    "-diff" | "binary" => Some(true),
+                    "diff" | "!diff" => Some(false),
+                    _ if token.starts_with("diff=") => Some(false),
+                    _ => None,
+                };
+            }
+        };
+        match token.strip_prefix(name) {
+            Some("") | Some("=true") => Some(true),
+            Some("=false") => Some(false),
+            _ if token.strip_prefix(['-', '!']) == Some(name) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Marker::Generated => "linguist-generated",
+            Marker::Vendored => "linguist-vendored",
+            Marker::NoDiff => "-diff",
+        })
+    }
+}
+
+/// The `.gitattributes` files of a directory tree, loaded as the walk reaches each directory.
+/// Patterns follow gitignore rules, except that a pattern matching a directory does not apply
+/// to the files inside it (`vendor/** linguist-vendored` marks a whole directory).
+#[derive(Default)]
+pub struct Attributes {
+    /// One matcher per marker for every directory with a `.gitattributes`. Setting lines are
+    /// added as ignore patterns and unsetting lines as whitelist (`!`) patterns, so the last
+    /// matching line wins as in git.
+    matchers: HashMap<PathBuf, Vec<(Marker, Gitignore)>>,
+}
+
+impl Attributes {
+    /// Reads `dir/.gitattributes`, if there is one.
+    pub fn load_dir(&mut self, dir: &Path) -> Result<()> {
+        let path = dir.join(ATTRIBUTES_FILENAME);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(JockeyError::Processing(format!("Failed to read '{}': {}", path.display(), e)))
+            }
+        };
+        self.load(dir, &contents)
+    }
+
+    /// Adds the contents of the `.gitattributes` in `dir`, e.g. as read from a git revision.
+    pub fn load(&mut self, dir: &Path, contents: &str) -> Result<()> {
+        let path = dir.join(ATTRIBUTES_FILENAME);
+        let mut matchers = Vec::new();
+        for marker in Marker::ALL {
+            let mut builder = GitignoreBuilder::new(dir);
+            let mut any = false;
+            for line in contents.lines().map(str::trim) {
+                // Macro definitions and comments carry no path patterns
+                if line.is_empty() || line.starts_with('#') || line.starts_with("[attr]") {
+                    continue;
+                }
+                let mut tokens = line.split_whitespace();
+                let Some(pattern) = tokens.next() else { continue };
+                let Some(set) = tokens.rev().find_map(|token| marker.parse(token)) else { continue };
+                let line = if set { pattern.to_string() } else { format!("!{}", pattern) };
+                builder.add_line(Some(path.clone()), &line).map_err(|e| {
+                    JockeyError::Config(format!("Invalid pattern in '{}': {}", path.display(), e))
+                })?;
+                any = true;
+            }
+            if any {
+                let matcher = builder.build().map_err(|e| {
+                    JockeyError::Config(format!("Invalid patterns in '{}': {}", path.display(), e))
+                })?;
+                matchers.push((marker, matcher));
+            }
+        }
+        if !matchers.is_empty() {
+            self.matchers.insert(dir.to_path_buf(), matchers);
+        }
+        Ok(())
+    }
+
+    /// The marker of the file at `path`, if any. Files in deeper directories override the
+    /// shallower ones, marker by marker.
+    pub fn marker(&self, path: &Path) -> Option<Marker> {
+        if self.matchers.is_empty() {
+            return None;
+        }
+        Marker::ALL.into_iter().find(|&marker| {
+            for dir in path.ancestors().skip(1) {
+                let Some(matchers) = self.matchers.get(dir) else { continue };
+                let decided = matchers
+                    .iter()
+                    .filter(|(m, _)| *m == marker)
+                    .map(|(_, matcher)| matcher.matched(path, false))
+                    .find(|matched| !matched.is_none());
+                match decided {
+                    Some(Match::Ignore(_)) => return true,
+                    Some(_) => return false,
+                    None => {}
+                }
+            }
+            false
+        })
+    }
+}
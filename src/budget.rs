@@ -0,0 +1,121 @@
+use crate::error::Result;
+use crate::output::{FileEntry, OmittedFile};
+use crate::tokens::Tokenizer;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Files modified within this window rank above older files of the same kind
+const RECENT_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Rough cost of the header and fences wrapped around every file, on top of its path
+const PER_FILE_OVERHEAD: usize = 8;
+
+/// An outline is only worth including when it is at most this fraction of the full file
+const MAX_OUTLINE_RATIO: f64 = 0.5;
+
+const ENTRYPOINTS: &[&str] = &[
+    // Sources
+    "main.rs", "lib.rs", "main.go", "main.py", "__main__.py", "app.py", "manage.py",
+    "index.js", "index.ts", "index.tsx", "main.js", "main.ts", "server.js", "app.js",
+    "main.c", "main.cpp", "Main.java", "Program.cs", "main.swift", "main.kt",
+    // Manifests
+    "Cargo.toml", "package.json", "pyproject.toml", "setup.py", "go.mod", "pom.xml",
+    "build.gradle", "Gemfile", "composer.json", "CMakeLists.txt", "Makefile", "Dockerfile",
+];
+
+// Line prefixes that mark a declaration worth keeping in an outline
+const DECLARATION_PREFIXES: &[&str] = &[
+    "pub ", "fn ", "async fn ", "struct ", "enum ", "trait ", "impl ", "impl<", "mod ", "type ",
+    "class ", "def ", "async def ", "function ", "async function ", "export ", "interface ",
+    "func ", "package ", "public ", "protected ", "private ", "#[derive", "# ", "## ",
+];
+
+/// A measured file, waiting to be ranked against the token budget
+pub struct Candidate {
+    pub entry: FileEntry,
+    pub modified: Option<SystemTime>,
+}
+
+pub struct Selection {
+    pub files: Vec<FileEntry>,
+    pub omitted: Vec<OmittedFile>,
+}
+
+/// Keeps the highest-ranked files that fit in `max_tokens`, replacing files that do not fit with
+/// an outline of their declarations where that helps. The tree counts against the budget, and
+/// kept files stay in their original order. Only files that need an outline are read into memory.
+pub fn select(candidates: Vec<Candidate>, tree: &str, max_tokens: usize, tokenizer: &dyn Tokenizer) -> Result<Selection> {
+    let mut remaining = max_tokens.saturating_sub(tokenizer.count(tree));
+    let now = SystemTime::now();
+
+    let mut ranked = candidates.into_iter().enumerate().collect::<Vec<_>>();
+    ranked.sort_by_key(|(_, candidate)| (rank(candidate, now), candidate.entry.tokens));
+
+    let mut kept = Vec::new();
+    let mut omitted = Vec::new();
+    for (index, candidate) in ranked {
+        let mut entry = candidate.entry;
+        let overhead = tokenizer.count(&entry.path) + PER_FILE_OVERHEAD;
+        if entry.tokens + overhead <= remaining {
+            remaining -= entry.tokens + overhead;
+            kept.push((index, entry));
+            continue;
+        }
+
+        let outline = outline(&entry.load()?.content);
+        let outline_tokens = tokenizer.count(&outline);
+        let worthwhile = !outline.is_empty() && (outline_tokens as f64) <= entry.tokens as f64 * MAX_OUTLINE_RATIO;
+        if worthwhile && outline_tokens + overhead <= remaining {
+            remaining -= outline_tokens + overhead;
+            entry.content = outline;
+            entry.source = None;
+            entry.tokens = outline_tokens;
+            entry.outlined = true;
+            kept.push((index, entry));
+        } else {
+            omitted.push((index, OmittedFile { path: entry.path, tokens: entry.tokens }));
+        }
+    }
+
+    kept.sort_by_key(|(index, _)| *index);
+    omitted.sort_by_key(|(index, _)| *index);
+    Ok(Selection {
+        files: kept.into_iter().map(|(_, entry)| entry).collect(),
+        omitted: omitted.into_iter().map(|(_, file)| file).collect(),
+    })
+}
+
+/// Lower ranks are kept first: READMEs, then entrypoints and manifests, then recently changed files.
+fn rank(candidate: &Candidate, now: SystemTime) -> u8 {
+    let name = Path::new(&candidate.entry.path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let recent = candidate
+        .modified
+        .and_then(|modified| now.duration_since(modified).ok())
+        .is_some_and(|age| age <= RECENT_WINDOW);
+
+    if name.to_lowercase().starts_with("readme") {
+        0
+    } else if ENTRYPOINTS.contains(&name.as_str()) {
+        1
+    } else if recent {
+        2
+    } else {
+        3
+    }
+}
+
+/// Reduces source text to its declaration lines.
+fn outline(content: &str) -> String {
+    let mut outline = String::new();
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if DECLARATION_PREFIXES.iter().any(|prefix| trimmed.starts_with(prefix)) {
+            outline.push_str(line.trim_end());
+            outline.push('\n');
+        }
+    }
+    outline
+}
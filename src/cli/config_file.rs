@@ -0,0 +1,277 @@
+use super::{Commands, Config, GenerateArgs, OutputFormat, PathStyle};
+use crate::error::{Result, JockeyError};
+use crate::split::SplitSize;
+use crate::tree::{TreeAnnotation, TreeOrder};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Project configuration file, read from the project root
+pub const CONFIG_FILENAME: &str = "jockey.toml";
+
+/// Defaults for `jockey generate`. Keys mirror the command-line flags, e.g. `max-tokens`.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Settings {
+    pub path: Option<String>,
+    /// One of md, txt, json, yaml or xml
+    pub format: Option<String>,
+    pub exclude: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub since: Option<String>,
+    pub staged: Option<bool>,
+    pub uncommitted: Option<bool>,
+    pub rev: Option<String>,
+    pub include_generated: Option<bool>,
+    pub git_history: Option<bool>,
+    pub history_window: Option<String>,
+    pub max_tokens: Option<usize>,
+    pub tree_order: Option<String>,
+    pub tree_annotate: Option<Vec<String>>,
+    pub tree_depth: Option<usize>,
+    pub tree_collapse_over: Option<usize>,
+    pub path_style: Option<String>,
+    pub max_file_size: Option<String>,
+    pub max_file_lines: Option<usize>,
+    pub split_size: Option<String>,
+    /// Relative paths are resolved against the project root
+    pub output: Option<PathBuf>,
+    pub stdout: Option<bool>,
+    pub parallel: Option<bool>,
+    pub verbose: Option<bool>,
+}
+
+/// Contents of `jockey.toml`: base settings plus `[profile.<name>]` sections
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigFile {
+    pub base: Settings,
+    pub profiles: HashMap<String, Settings>,
+}
+
+impl ConfigFile {
+    pub fn parse(input: &str) -> Result<Self> {
+        let invalid = |e: toml::de::Error| {
+            JockeyError::Config(format!("Invalid {}: {}", CONFIG_FILENAME, e))
+        };
+        let mut table = input.parse::<toml::Table>().map_err(invalid)?;
+        let profiles = match table.remove("profile") {
+            Some(profiles) => profiles.try_into().map_err(invalid)?,
+            None => HashMap::new(),
+        };
+        let base = toml::Value::Table(table).try_into().map_err(invalid)?;
+        Ok(Self { base, profiles })
+    }
+
+    /// Reads `jockey.toml` from `root`, if there is one.
+    pub fn load(root: &Path) -> Result<Option<Self>> {
+        let path = root.join(CONFIG_FILENAME);
+        match std::fs::read_to_string(&path) {
+            Ok(input) => Self::parse(&input).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(JockeyError::Config(format!("Failed to read {}: {}", path.display(), e))),
+        }
+    }
+
+    /// Base settings with the named profile laid over them.
+    pub fn resolve(&self, profile: Option<&str>) -> Result<Settings> {
+        let Some(name) = profile else {
+            return Ok(self.base.clone());
+        };
+        let overrides = self.profiles.get(name).ok_or_else(|| {
+            let mut known = self.profiles.keys().map(String::as_str).collect::<Vec<_>>();
+            known.sort_unstable();
+            JockeyError::Config(format!(
+                "Unknown profile '{}' (profiles in {}: {})",
+                name,
+                CONFIG_FILENAME,
+                if known.is_empty() { "none".to_string() } else { known.join(", ") }
+            ))
+        })?;
+        Ok(overrides.clone().or(self.base.clone()))
+    }
+}
+
+impl Settings {
+    /// Takes each value from `self`, falling back to `base`.
+    fn or(self, base: Settings) -> Settings {
+        Settings {
+            path: self.path.or(base.path),
+            format: self.format.or(base.format),
+            exclude: self.exclude.or(base.exclude),
+            include: self.include.or(base.include),
+            since: self.since.or(base.since),
+            staged: self.staged.or(base.staged),
+            uncommitted: self.uncommitted.or(base.uncommitted),
+            rev: self.rev.or(base.rev),
+            include_generated: self.include_generated.or(base.include_generated),
+            git_history: self.git_history.or(base.git_history),
+            history_window: self.history_window.or(base.history_window),
+            max_tokens: self.max_tokens.or(base.max_tokens),
+            tree_order: self.tree_order.or(base.tree_order),
+            tree_annotate: self.tree_annotate.or(base.tree_annotate),
+            tree_depth: self.tree_depth.or(base.tree_depth),
+            tree_collapse_over: self.tree_collapse_over.or(base.tree_collapse_over),
+            path_style: self.path_style.or(base.path_style),
+            max_file_size: self.max_file_size.or(base.max_file_size),
+            max_file_lines: self.max_file_lines.or(base.max_file_lines),
+            split_size: self.split_size.or(base.split_size),
+            output: self.output.or(base.output),
+            stdout: self.stdout.or(base.stdout),
+            parallel: self.parallel.or(base.parallel),
+            verbose: self.verbose.or(base.verbose),
+        }
+    }
+
+    /// Fills in every `generate` option that was not given on the command line. `matches` are
+    /// the matches of the `generate` subcommand.
+    pub fn apply(self, command: &mut Commands, matches: &ArgMatches, root: &Path) -> Result<()> {
+        let Commands::Generate(args) = command else {
+            return Ok(());
+        };
+        let GenerateArgs {
+            path, md, json, txt, yaml, xml, exclude, include, since, staged, uncommitted, rev, include_generated, git_history, history_window, max_tokens, tree_order, tree_annotate,
+            tree_depth, tree_collapse_over, path_style, max_file_size, max_file_lines, split_size,
+            output, stdout, parallel, verbose, ..
+        } = &mut **args;
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+
+        macro_rules! fill {
+            ($field:ident, $value:expr) => {
+                if unset(stringify!($field)) {
+                    if let Some(value) = $value {
+                        *$field = value;
+                    }
+                }
+            };
+        }
+        // Flags can be switched on in the file and off again with their `--no-` form
+        macro_rules! fill_flag {
+            ($field:ident, $value:expr) => {
+                if unset(concat!("no_", stringify!($field))) {
+                    fill!($field, $value);
+                }
+            };
+        }
+
+        fill!(path, self.path.map(Some));
+        fill!(exclude, self.exclude.map(|patterns| Some(patterns.join(","))));
+        fill!(include, self.include);
+        fill_flag!(include_generated, self.include_generated);
+        fill_flag!(git_history, self.git_history);
+        fill!(history_window, self.history_window);
+        fill!(max_tokens, self.max_tokens.map(Some));
+        fill!(tree_order, self.tree_order.map(|v| parse_enum::<TreeOrder>("tree-order", &v)).transpose()?);
+        fill!(tree_annotate, self.tree_annotate
+            .map(|values| values.iter().map(|v| parse_enum("tree-annotate", v)).collect::<Result<Vec<TreeAnnotation>>>())
+            .transpose()?);
+        fill!(tree_depth, self.tree_depth.map(Some));
+        fill!(tree_collapse_over, self.tree_collapse_over.map(Some));
+        fill!(path_style, self.path_style.map(|v| parse_value::<PathStyle>("path-style", &v)).transpose()?);
+        fill!(max_file_size, self.max_file_size.map(|v| parse_value::<SplitSize>("max-file-size", &v).map(Some)).transpose()?);
+        fill!(max_file_lines, self.max_file_lines.map(Some));
+        // Writing to stdout excludes an output file and parts, so whichever side the command
+        // line picks replaces the file's choice instead of conflicting with it
+        if unset("stdout") {
+            fill!(split_size, self.split_size.map(|v| parse_value::<SplitSize>("split-size", &v).map(Some)).transpose()?);
+            fill!(output, self.output.map(|file| Some(root.join(file))));
+        }
+        if unset("output") && unset("split_size") {
+            fill_flag!(stdout, self.stdout);
+        }
+        fill_flag!(parallel, self.parallel);
+        fill_flag!(verbose, self.verbose);
+
+        // Like the format flags, the change selections and the revision exclude each other, so a
+        // selection on the command line replaces the file's selection as a whole
+        let changes_given = ["since", "staged", "uncommitted", "rev"].iter().any(|id| !unset(id));
+        if !changes_given {
+            fill!(since, self.since.map(Some));
+            fill_flag!(staged, self.staged);
+            fill_flag!(uncommitted, self.uncommitted);
+            fill!(rev, self.rev.map(Some));
+        }
+
+        // Any format flag on the command line wins over the file's format
+        let format_given = ["md", "json", "txt", "yaml", "xml"].iter().any(|id| !unset(id));
+        if let (false, Some(format)) = (format_given, self.format) {
+            let format = parse_enum::<OutputFormat>("format", &format)?;
+            *md = matches!(format, OutputFormat::Md);
+            *json = matches!(format, OutputFormat::Json);
+            *txt = matches!(format, OutputFormat::Txt);
+            *yaml = matches!(format, OutputFormat::Yaml);
+            *xml = matches!(format, OutputFormat::Xml);
+        }
+
+        check_conflicts(args)
+    }
+}
+
+/// Applies `jockey.toml` from the project root to a parsed `generate` command, using the
+/// profile selected with `--profile`.
+pub fn apply_config_file(config: &mut Config, matches: &ArgMatches) -> Result<()> {
+    let profile = match &config.command {
+        Commands::Generate(args) => args.profile.clone(),
+        _ => return Ok(()),
+    };
+    let root = Config::get_root_dir()?;
+    let Some(file) = ConfigFile::load(&root)? else {
+        return match profile {
+            Some(name) => Err(JockeyError::Config(format!(
+                "Profile '{}' requested but there is no {} in {}",
+                name,
+                CONFIG_FILENAME,
+                root.display()
+            ))),
+            None => Ok(()),
+        };
+    };
+    file.resolve(profile.as_deref())?.apply(&mut config.command, matches, &root)
+}
+
+/// Options that exclude each other, by their names in `jockey.toml`. clap only checks the
+/// command line, so the merged options are checked again.
+const CONFLICTS: &[(&str, &str)] = &[
+    ("output", "stdout"),
+    ("stdout", "split-size"),
+    ("since", "staged"),
+    ("since", "uncommitted"),
+    ("staged", "uncommitted"),
+    ("rev", "since"),
+    ("rev", "staged"),
+    ("rev", "uncommitted"),
+];
+
+fn check_conflicts(args: &GenerateArgs) -> Result<()> {
+    let is_set = |key: &str| match key {
+        "output" => args.output.is_some(),
+        "stdout" => args.stdout,
+        "split-size" => args.split_size.is_some(),
+        "since" => args.since.is_some(),
+        "staged" => args.staged,
+        "uncommitted" => args.uncommitted,
+        "rev" => args.rev.is_some(),
+        _ => false,
+    };
+    match CONFLICTS.iter().find(|(a, b)| is_set(a) && is_set(b)) {
+        Some((a, b)) => Err(JockeyError::Config(format!(
+            "'{}' and '{}' cannot be used together (after applying {})",
+            a, b, CONFIG_FILENAME
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn parse_value<T: FromStr<Err = String>>(key: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|e| {
+        JockeyError::Config(format!("Invalid {} in {}: {}", key, CONFIG_FILENAME, e))
+    })
+}
+
+fn parse_enum<T: ValueEnum>(key: &str, value: &str) -> Result<T> {
+    T::from_str(value, true).map_err(|e| {
+        JockeyError::Config(format!("Invalid {} in {}: {}", key, CONFIG_FILENAME, e))
+    })
+}
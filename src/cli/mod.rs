@@ -1,14 +1,69 @@
-use clap::{Parser, Subcommand, ValueEnum};
-use std::path::PathBuf;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use crate::error::{Result, JockeyError};
+use crate::git::ChangeSet;
+use crate::split::SplitSize;
+use crate::tree::{TreeAnnotation, TreeOrder};
+use crate::truncate::FileLimits;
 use glob;
 
+mod config_file;
+
+pub use config_file::{apply_config_file, ConfigFile, Settings, CONFIG_FILENAME};
+
 #[derive(Clone, Debug, ValueEnum)]
 pub enum OutputFormat {
     Md,
     Txt,
     Json,
     Yaml,
+    Xml,
+}
+
+/// How file paths are written in an image
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum PathStyle {
+    /// Absolute paths on the machine that generated the image
+    Absolute,
+    /// Paths relative to the processed directory (default)
+    #[default]
+    Relative,
+    /// Relative paths under a fixed top-level name, e.g. `prefixed:app` gives `app/src/main.rs`
+    Prefixed(String),
+}
+
+impl FromStr for PathStyle {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "absolute" => Ok(PathStyle::Absolute),
+            "relative" => Ok(PathStyle::Relative),
+            _ => match value.strip_prefix("prefixed:") {
+                Some(name) if !name.is_empty() => Ok(PathStyle::Prefixed(name.trim_matches('/').to_string())),
+                _ => Err(format!("invalid path style '{}' (use absolute, relative or prefixed:<name>)", value)),
+            },
+        }
+    }
+}
+
+impl PathStyle {
+    /// Formats `path`, a file under `root`, for display in the image.
+    pub fn display(&self, path: &Path, root: &Path) -> String {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let display = match self {
+            PathStyle::Absolute => return path.to_string_lossy().into_owned(),
+            PathStyle::Relative => relative.to_path_buf(),
+            PathStyle::Prefixed(name) => Path::new(name).join(relative),
+        };
+        // Images use forward slashes on every platform
+        display
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -18,16 +73,180 @@ pub struct Config {
     pub command: Commands,
 }
 
+/// Options of `jockey generate`
+#[derive(Args, Debug)]
+pub struct GenerateArgs {
+    /// Specific subdirectory to process (optional, uses fuzzy matching)
+    #[arg(long)]
+    pub path: Option<String>,
+
+    /// Use the [profile.<name>] section of jockey.toml on top of its base settings
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Output in markdown format (default)
+    #[arg(long, default_value_t = true, conflicts_with_all = &["json", "txt", "yaml", "xml"])]
+    pub md: bool,
+
+    /// Output in JSON format
+    #[arg(long)]
+    pub json: bool,
+
+    /// Output in plain text format
+    #[arg(long)]
+    pub txt: bool,
+
+    /// Output in YAML format
+    #[arg(long)]
+    pub yaml: bool,
+
+    /// Output as XML-tagged documents for LLM prompts
+    #[arg(long)]
+    pub xml: bool,
+
+    /// Exclude patterns (comma-separated)
+    #[arg(long)]
+    pub exclude: Option<String>,
+
+    /// Only include files changed since the merge base of this git ref and HEAD, plus uncommitted changes
+    #[arg(long, conflicts_with_all = &["staged", "uncommitted"])]
+    pub since: Option<String>,
+
+    /// Only include files with staged changes
+    #[arg(long, conflicts_with = "uncommitted")]
+    pub staged: bool,
+
+    /// Turn off --staged set in jockey.toml
+    #[arg(long, overrides_with = "staged")]
+    pub no_staged: bool,
+
+    /// Only include files with staged, unstaged or untracked changes
+    #[arg(long)]
+    pub uncommitted: bool,
+
+    /// Turn off --uncommitted set in jockey.toml
+    #[arg(long, overrides_with = "uncommitted")]
+    pub no_uncommitted: bool,
+
+    /// Read the files and tree from this git commit, tag or branch instead of the working directory
+    #[arg(long, conflicts_with_all = &["since", "staged", "uncommitted"])]
+    pub rev: Option<String>,
+
+    /// Include files marked linguist-generated, linguist-vendored or -diff in .gitattributes
+    #[arg(long)]
+    pub include_generated: bool,
+
+    /// Turn off --include-generated set in jockey.toml
+    #[arg(long, overrides_with = "include_generated")]
+    pub no_include_generated: bool,
+
+    /// Caption every file with its last commit and how often it changed within --history-window
+    #[arg(long)]
+    pub git_history: bool,
+
+    /// Turn off --git-history set in jockey.toml
+    #[arg(long, overrides_with = "git_history")]
+    pub no_git_history: bool,
+
+    /// Period the --git-history churn is counted over, in git's date syntax (e.g. "2 weeks", "90 days")
+    #[arg(long, default_value = "1 month")]
+    pub history_window: String,
+
+    /// Only include files matching this glob (repeatable), e.g. --include 'src/**/*.rs' --include Cargo.toml
+    #[arg(long)]
+    pub include: Vec<String>,
+
+    /// Token budget; lower-priority files are outlined or omitted to fit
+    #[arg(long)]
+    pub max_tokens: Option<usize>,
+
+    /// Order of entries in the directory tree
+    #[arg(long, value_enum, default_value_t = TreeOrder::DirsFirst)]
+    pub tree_order: TreeOrder,
+
+    /// Annotate the tree with per-file values and per-directory totals (comma-separated: size,lines,tokens)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub tree_annotate: Vec<TreeAnnotation>,
+
+    /// Render the tree only this many levels deep; deeper directories are collapsed
+    #[arg(long)]
+    pub tree_depth: Option<usize>,
+
+    /// Collapse directories with more than this many entries in the tree
+    #[arg(long)]
+    pub tree_collapse_over: Option<usize>,
+
+    /// How file paths are written: relative (default), absolute, or prefixed:<name>
+    #[arg(long, default_value = "relative")]
+    pub path_style: PathStyle,
+
+    /// Keep only the first and last lines of files larger than this (e.g. 50kb, 5000tokens)
+    #[arg(long)]
+    pub max_file_size: Option<SplitSize>,
+
+    /// Keep only the first and last lines of files longer than this many lines
+    #[arg(long)]
+    pub max_file_lines: Option<usize>,
+
+    /// Split the image into numbered parts of at most this size (e.g. 500kb, 2mb, 50000tokens, 100kt)
+    #[arg(long)]
+    pub split_size: Option<SplitSize>,
+
+    /// Write the image to this path instead of a dated file in jockey-img/
+    #[arg(long, short = 'o', conflicts_with = "stdout")]
+    pub output: Option<PathBuf>,
+
+    /// Write the image to stdout; the summary goes to stderr
+    #[arg(long, conflicts_with = "split_size")]
+    pub stdout: bool,
+
+    /// Turn off --stdout set in jockey.toml
+    #[arg(long, overrides_with = "stdout")]
+    pub no_stdout: bool,
+
+    /// Enable parallel processing
+    #[arg(long)]
+    pub parallel: bool,
+
+    /// Turn off --parallel set in jockey.toml
+    #[arg(long, overrides_with = "parallel")]
+    pub no_parallel: bool,
+
+    /// Enable verbose logging
+    #[arg(long)]
+    pub verbose: bool,
+
+    /// Turn off --verbose set in jockey.toml
+    #[arg(long, overrides_with = "verbose")]
+    pub no_verbose: bool,
+}
+
+impl Default for GenerateArgs {
+    /// The options of a bare `jockey generate`, so the defaults are the ones clap fills in
+    fn default() -> Self {
+        match Config::parse_from(["jockey", "generate"]).command {
+            Commands::Generate(args) => *args,
+            _ => unreachable!("`generate` parses to Commands::Generate"),
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Generate a structured representation of the repository
-    Generate {
-        /// Specific subdirectory to process (optional, uses fuzzy matching)
+    Generate(Box<GenerateArgs>),
+
+    /// Build a code review image: the diff and new content of each changed file, plus related files
+    Review {
+        /// Base to review against; changes are taken from its merge base with HEAD, plus uncommitted changes
+        base: String,
+
+        /// Leave out related files such as the tests of changed sources
         #[arg(long)]
-        path: Option<String>,
+        no_related: bool,
 
         /// Output in markdown format (default)
-        #[arg(long, default_value_t = true, conflicts_with_all = &["json", "txt", "yaml"])]
+        #[arg(long, default_value_t = true, conflicts_with_all = &["json", "txt", "yaml", "xml"])]
         md: bool,
 
         /// Output in JSON format
@@ -42,17 +261,56 @@ pub enum Commands {
         #[arg(long)]
         yaml: bool,
 
-    /// Exclude patterns (comma-separated)
-    #[arg(long)]
-        exclude: Option<String>,
+        /// Output as XML-tagged documents for LLM prompts
+        #[arg(long)]
+        xml: bool,
 
-    /// Enable parallel processing
-    #[arg(long)]
-        parallel: bool,
+        /// Write the image to this path instead of a dated file in jockey-img/
+        #[arg(long, short = 'o', conflicts_with = "stdout")]
+        output: Option<PathBuf>,
 
-    /// Enable verbose logging
-    #[arg(long)]
-        verbose: bool,
+        /// Write the image to stdout; the summary goes to stderr
+        #[arg(long)]
+        stdout: bool,
+    },
+
+    /// Recreate the files stored in an existing Jockey image
+    Unpack {
+        /// Image produced by `jockey generate` (md, txt, json, yaml or xml)
+        image: PathBuf,
+
+        /// Directory to recreate the files in (defaults to the current directory)
+        #[arg(long)]
+        into: Option<PathBuf>,
+
+        /// Overwrite files that already exist
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Write the files of an edited image back to the working tree
+    Apply {
+        /// Edited image (markdown `## File:` sections, JSON `Repository`, or any other format)
+        image: PathBuf,
+
+        /// Subdirectory the image was generated from (relative to project root)
+        #[arg(long)]
+        path: Option<String>,
+
+        /// Write the changes without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Apply unified diffs (plain or fenced ```diff blocks) to the project
+    Patch {
+        /// Files containing the diffs (`-` reads from stdin)
+        #[arg(required = true)]
+        patches: Vec<PathBuf>,
+
+        /// Report which hunks apply without writing anything
+        #[arg(long)]
+        dry_run: bool,
     },
 }
 
@@ -117,17 +375,23 @@ const ROOT_INDICATORS: &[&str] = &[
 impl Config {
     pub fn format(&self) -> OutputFormat {
         match &self.command {
-            Commands::Generate { json, txt, yaml, .. } => {
-                if *json {
-                    OutputFormat::Json
-                } else if *txt {
-                    OutputFormat::Txt
-                } else if *yaml {
-                    OutputFormat::Yaml
-                } else {
-                    OutputFormat::Md
-                }
-            }
+            Commands::Generate(args) => Self::selected_format(args.json, args.txt, args.yaml, args.xml),
+            Commands::Review { json, txt, yaml, xml, .. } => Self::selected_format(*json, *txt, *yaml, *xml),
+            _ => OutputFormat::Md,
+        }
+    }
+
+    fn selected_format(json: bool, txt: bool, yaml: bool, xml: bool) -> OutputFormat {
+        if json {
+            OutputFormat::Json
+        } else if txt {
+            OutputFormat::Txt
+        } else if yaml {
+            OutputFormat::Yaml
+        } else if xml {
+            OutputFormat::Xml
+        } else {
+            OutputFormat::Md
         }
     }
 
@@ -152,9 +416,9 @@ impl Config {
 
             // If no subdirs found, check for specific root indicators as fallback
             let found = ROOT_INDICATORS.iter().any(|&indicator| {
-                if indicator.ends_with('/') {
+                if let Some(dir) = indicator.strip_suffix('/') {
                     // Handle directory indicators
-                    current.join(&indicator[..indicator.len()-1]).is_dir()
+                    current.join(dir).is_dir()
                 } else if indicator.contains('*') {
                     // Handle glob patterns
                     if let Ok(entries) = std::fs::read_dir(&current) {
@@ -193,61 +457,183 @@ impl Config {
     pub fn get_target_dir(&self) -> Result<PathBuf> {
         let root = Self::get_root_dir()?;
         
-        match &self.command {
-            Commands::Generate { path, .. } => {
-                if let Some(path) = path {
-                    // Normalize and resolve the path
-                    let target = if path.starts_with("/") {
-                        PathBuf::from(path)
-                    } else {
-                        root.join(path)
-                    };
-
-                    // Canonicalize to resolve any '..' or '.' components
-                    let canonical = target.canonicalize().map_err(|e| {
-                        JockeyError::Config(format!(
-                            "Failed to resolve path '{}': {}",
-                            path, e
-                        ))
-                    })?;
-
-                    // Ensure the path is within the project root
-                    if !canonical.starts_with(&root) {
-                        return Err(JockeyError::Config(format!(
-                            "Path '{}' is outside the project root",
-                            path
-                        )));
-                    }
+        let path = match &self.command {
+            Commands::Generate(args) => &args.path,
+            Commands::Apply { path, .. } => path,
+            _ => return Ok(root),
+        };
 
-                    // Ensure it's a directory
-                    if !canonical.is_dir() {
-        return Err(JockeyError::Config(format!(
-                            "Path '{}' is not a directory",
-                            path
-                        )));
-                    }
+        if let Some(path) = path {
+            // Normalize and resolve the path
+            let target = if path.starts_with("/") {
+                PathBuf::from(path)
+            } else {
+                root.join(path)
+            };
 
-                    Ok(canonical)
-                } else {
-                    Ok(root)
-                }
+            // Canonicalize to resolve any '..' or '.' components
+            let canonical = target.canonicalize().map_err(|e| {
+                JockeyError::Config(format!(
+                    "Failed to resolve path '{}': {}",
+                    path, e
+                ))
+            })?;
+
+            // Ensure the path is within the project root
+            if !canonical.starts_with(&root) {
+                return Err(JockeyError::Config(format!(
+                    "Path '{}' is outside the project root",
+                    path
+                )));
             }
+
+            // Ensure it's a directory
+            if !canonical.is_dir() {
+                return Err(JockeyError::Config(format!(
+                    "Path '{}' is not a directory",
+                    path
+                )));
+            }
+
+            Ok(canonical)
+        } else {
+            Ok(root)
         }
     }
 
     pub fn is_parallel(&self) -> bool {
         match &self.command {
-            Commands::Generate { parallel, .. } => *parallel
+            Commands::Generate(args) => args.parallel,
+            _ => false,
         }
     }
 
     pub fn exclude_patterns(&self) -> Option<&String> {
         match &self.command {
-            Commands::Generate { exclude, .. } => exclude.as_ref()
+            Commands::Generate(args) => args.exclude.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn include_patterns(&self) -> &[String] {
+        match &self.command {
+            Commands::Generate(args) => &args.include,
+            _ => &[],
+        }
+    }
+
+    pub fn max_tokens(&self) -> Option<usize> {
+        match &self.command {
+            Commands::Generate(args) => args.max_tokens,
+            _ => None,
+        }
+    }
+
+    pub fn tree_order(&self) -> TreeOrder {
+        match &self.command {
+            Commands::Generate(args) => args.tree_order,
+            _ => TreeOrder::default(),
+        }
+    }
+
+    pub fn tree_annotations(&self) -> Vec<TreeAnnotation> {
+        match &self.command {
+            Commands::Generate(args) => args.tree_annotate.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Depth limit and width limit for the rendered tree
+    pub fn tree_limits(&self) -> (Option<usize>, Option<usize>) {
+        match &self.command {
+            Commands::Generate(args) => (args.tree_depth, args.tree_collapse_over),
+            _ => (None, None),
+        }
+    }
+
+    pub fn path_style(&self) -> PathStyle {
+        match &self.command {
+            Commands::Generate(args) => args.path_style.clone(),
+            _ => PathStyle::default(),
+        }
+    }
+
+    pub fn file_limits(&self) -> FileLimits {
+        match &self.command {
+            Commands::Generate(args) => FileLimits {
+                max_size: args.max_file_size,
+                max_lines: args.max_file_lines,
+            },
+            _ => FileLimits::default(),
+        }
+    }
+
+    /// Git changes the files of the image are restricted to, if any
+    pub fn changes(&self) -> Option<ChangeSet> {
+        match &self.command {
+            Commands::Generate(args) => match &**args {
+                GenerateArgs { since: Some(reference), .. } => Some(ChangeSet::Since(reference.clone())),
+                GenerateArgs { staged: true, .. } => Some(ChangeSet::Staged),
+                GenerateArgs { uncommitted: true, .. } => Some(ChangeSet::Uncommitted),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Git revision the files are read from instead of the working directory, if any
+    pub fn revision(&self) -> Option<&str> {
+        match &self.command {
+            Commands::Generate(args) => args.rev.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn include_generated(&self) -> bool {
+        match &self.command {
+            Commands::Generate(args) => args.include_generated,
+            _ => false,
+        }
+    }
+
+    /// Window for the per-file git history, when --git-history is on
+    pub fn git_history(&self) -> Option<&str> {
+        match &self.command {
+            Commands::Generate(args) if args.git_history => Some(&args.history_window),
+            _ => None,
+        }
+    }
+
+    pub fn output_path(&self) -> Option<&PathBuf> {
+        match &self.command {
+            Commands::Generate(args) => args.output.as_ref(),
+            Commands::Review { output, .. } => output.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn to_stdout(&self) -> bool {
+        match &self.command {
+            Commands::Generate(args) => args.stdout,
+            Commands::Review { stdout, .. } => *stdout,
+            _ => false,
+        }
+    }
+
+    pub fn split_size(&self) -> Option<SplitSize> {
+        match &self.command {
+            Commands::Generate(args) => args.split_size,
+            _ => None,
         }
     }
 }
 
+/// Parses the command line, filling in unset `generate` options from `jockey.toml`.
 pub fn parse_args() -> Result<Config> {
-    Ok(Config::parse())
+    let matches = Config::command().get_matches();
+    let mut config = Config::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(("generate", generate)) = matches.subcommand() {
+        apply_config_file(&mut config, generate)?;
+    }
+    Ok(config)
 } 
\ No newline at end of file
@@ -12,6 +12,12 @@ pub struct CompressionPool {
     _private: (),  // Prevent direct construction
 }
 
+impl Default for CompressionPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CompressionPool {
     pub fn new() -> Self {
         Self { _private: () }
@@ -0,0 +1,75 @@
+use crate::error::{Result, JockeyError};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+
+/// Returns the UTF-16 encoding announced by a byte order mark, if any.
+pub fn utf16_bom(bytes: &[u8]) -> Option<&'static Encoding> {
+    match Encoding::for_bom(bytes) {
+        Some((encoding, _)) if encoding == UTF_16LE || encoding == UTF_16BE => Some(encoding),
+        _ => None,
+    }
+}
+
+/// Guesses the legacy encoding of bytes that are not UTF-8 (Windows-1252, Shift-JIS, ...).
+pub fn guess_legacy(bytes: &[u8]) -> &'static Encoding {
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, false)
+}
+
+/// Decodes `bytes` as `encoding`, dropping a leading BOM. Returns `None` if the bytes are not
+/// valid in that encoding.
+pub fn decode_as(bytes: &[u8], encoding: &'static Encoding) -> Option<String> {
+    let (text, had_errors) = encoding.decode_with_bom_removal(bytes);
+    (!had_errors).then(|| text.into_owned())
+}
+
+/// Decodes file contents read back from disk, using the encoding recorded for the file (UTF-8
+/// when there is none).
+pub fn decode(bytes: &[u8], encoding: Option<&str>) -> Result<String> {
+    match encoding {
+        None => std::str::from_utf8(bytes).map(str::to_string).map_err(|_| {
+            JockeyError::Processing("file is no longer valid UTF-8".to_string())
+        }),
+        Some(name) => decode_as(bytes, lookup(name)?).ok_or_else(|| {
+            JockeyError::Processing(format!("file is no longer valid {}", name))
+        }),
+    }
+}
+
+/// Encodes text for writing back to a file that was originally in `encoding` (UTF-8 when there
+/// is none). UTF-16 files get their byte order mark back.
+pub fn encode(text: &str, encoding: Option<&str>) -> Result<Vec<u8>> {
+    let Some(name) = encoding else {
+        return Ok(text.as_bytes().to_vec());
+    };
+    let encoding = lookup(name)?;
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        // encoding_rs only decodes UTF-16, so the code units are written by hand
+        let little_endian = encoding == UTF_16LE;
+        let mut bytes = Vec::with_capacity(2 + text.len() * 2);
+        for unit in std::iter::once(0xFEFF).chain(text.encode_utf16()) {
+            if little_endian {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            } else {
+                bytes.extend_from_slice(&unit.to_be_bytes());
+            }
+        }
+        return Ok(bytes);
+    }
+
+    let (bytes, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(JockeyError::Processing(format!(
+            "text contains characters that cannot be written as {}",
+            name
+        )));
+    }
+    Ok(bytes.into_owned())
+}
+
+fn lookup(name: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(name.as_bytes()).ok_or_else(|| {
+        JockeyError::InvalidFormat(format!("Unknown encoding '{}'", name))
+    })
+}
@@ -16,6 +16,9 @@ pub enum JockeyError {
 
     #[error("Configuration error: {0}")]
     Config(String),
+
+    #[error("Git error: {0}")]
+    Git(String),
 }
 
 pub type Result<T> = std::result::Result<T, JockeyError>; 
\ No newline at end of file
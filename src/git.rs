@@ -0,0 +1,340 @@
+use crate::error::{Result, JockeyError};
+use crate::output::{FileHistory, GitMetadata};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Separators for `git log` formats, which cannot occur in commit subjects or paths
+const RECORD_START: char = '\u{1e}';
+const FIELD_SEPARATOR: char = '\u{1f}';
+
+/// Which changes to restrict an image to
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeSet {
+    /// Changed since the merge base of a ref and HEAD, including uncommitted and untracked files
+    Since(String),
+    /// Staged in the index
+    Staged,
+    /// Staged, unstaged and untracked changes relative to HEAD
+    Uncommitted,
+}
+
+impl fmt::Display for ChangeSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeSet::Since(reference) => write!(f, "since {}", reference),
+            ChangeSet::Staged => write!(f, "staged"),
+            ChangeSet::Uncommitted => write!(f, "uncommitted"),
+        }
+    }
+}
+
+/// Runs `git` in `dir` and returns its stdout.
+pub(crate) fn run(dir: &Path, args: &[&str]) -> Result<String> {
+    String::from_utf8(run_bytes(dir, args)?).map_err(|e| {
+        JockeyError::Git(format!("git {} printed invalid UTF-8: {}", args.join(" "), e))
+    })
+}
+
+/// Runs `git` in `dir` and returns its raw stdout, for output that may quote file contents.
+fn run_bytes(dir: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| JockeyError::Git(format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(JockeyError::Git(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// Root of the work tree that `dir` belongs to
+pub fn toplevel(dir: &Path) -> Result<PathBuf> {
+    Ok(PathBuf::from(run(dir, &["rev-parse", "--show-toplevel"])?.trim_end()))
+}
+
+/// Directories above `dir` up to the top of its work tree, nearest first and spelled like `dir`.
+/// Their ignore and attribute files reach into `dir`. Empty outside a work tree.
+pub fn ancestors_in_work_tree(dir: &Path) -> Vec<PathBuf> {
+    let Some(top) = toplevel(dir).ok().and_then(|top| top.canonicalize().ok()) else {
+        return Vec::new();
+    };
+    let ancestors = dir.ancestors().skip(1).collect::<Vec<_>>();
+    match ancestors.iter().position(|ancestor| ancestor.canonicalize().is_ok_and(|a| a == top)) {
+        Some(end) => ancestors[..=end].iter().map(|ancestor| ancestor.to_path_buf()).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Commit where HEAD branched off `reference`
+pub fn merge_base(dir: &Path, reference: &str) -> Result<String> {
+    Ok(run(dir, &["merge-base", reference, "HEAD"])?.trim_end().to_string())
+}
+
+/// Unified diff of `path` (relative to the top of the work tree) between `base` and the working
+/// tree. Empty for files git does not track.
+pub fn diff_file(toplevel: &Path, base: &str, path: &str) -> Result<String> {
+    let diff = run_bytes(toplevel, &["diff", "--no-color", "--no-ext-diff", base, "--", path])?;
+    // Lines of files in legacy encodings are not valid UTF-8; the diff is only for reading
+    Ok(String::from_utf8_lossy(&diff).into_owned())
+}
+
+/// Absolute paths of the files in `changes`. Deleted files are included, so callers should
+/// match the result against files that exist.
+pub fn changed_files(dir: &Path, changes: &ChangeSet) -> Result<HashSet<PathBuf>> {
+    let toplevel = toplevel(dir)?;
+    // Both commands print NUL-separated paths relative to the top of the work tree; ls-files
+    // only lists the directory it runs in, so it runs at the top as well
+    let untracked = || run(&toplevel, &["ls-files", "-z", "--others", "--exclude-standard"]);
+    let listings = match changes {
+        ChangeSet::Since(reference) => {
+            let base = merge_base(dir, reference)?;
+            vec![run(dir, &["diff", "-z", "--name-only", &base])?, untracked()?]
+        }
+        ChangeSet::Staged => vec![run(dir, &["diff", "-z", "--name-only", "--cached"])?],
+        ChangeSet::Uncommitted => vec![run(dir, &["diff", "-z", "--name-only", "HEAD"])?, untracked()?],
+    };
+    Ok(listings
+        .iter()
+        .flat_map(|listing| listing.split('\0'))
+        .filter(|path| !path.is_empty())
+        .map(|path| toplevel.join(path))
+        .collect())
+}
+
+/// Describes the revision checked out at `dir`, or `None` outside a git repository (or without
+/// git, or before the first commit).
+pub fn metadata(dir: &Path) -> Option<GitMetadata> {
+    let mut metadata = describe(dir, "HEAD")?;
+    metadata.branch = run(dir, &["rev-parse", "--abbrev-ref", "HEAD"])
+        .ok()
+        .map(|branch| branch.trim_end().to_string())
+        .filter(|branch| branch != "HEAD");
+    // One line per changed or untracked file, renames included
+    let modified = run(dir, &["status", "--porcelain"]).ok()?.lines().count();
+    metadata.dirty = Some(modified > 0);
+    metadata.modified = Some(modified);
+    Some(metadata)
+}
+
+/// Describes `rev` rather than the checkout. The branch is only named when `rev` is a local
+/// branch, and the working tree state is left unset, since the image does not come from it.
+pub fn revision_metadata(dir: &Path, rev: &str) -> Option<GitMetadata> {
+    let mut metadata = describe(dir, rev)?;
+    metadata.branch = run(dir, &["rev-parse", "--verify", "--quiet", &format!("refs/heads/{}", rev)])
+        .ok()
+        .map(|_| rev.to_string());
+    Some(metadata)
+}
+
+/// Commit, subject and remote of `rev`
+fn describe(dir: &Path, rev: &str) -> Option<GitMetadata> {
+    let log = run(dir, &["log", "-1", "--format=%H%x00%s", rev, "--"]).ok()?;
+    let (commit, subject) = log.trim_end().split_once('\0')?;
+    let remote = run(dir, &["remote", "get-url", "origin"])
+        .or_else(|_| {
+            let remotes = run(dir, &["remote"])?;
+            let first = remotes.lines().next().unwrap_or_default();
+            run(dir, &["remote", "get-url", first])
+        })
+        .ok()
+        .map(|url| strip_credentials(url.trim_end()));
+    Some(GitMetadata {
+        commit: commit.to_string(),
+        subject: subject.to_string(),
+        remote,
+        ..GitMetadata::default()
+    })
+}
+
+/// Removes a user name and password or token from a remote URL, so they do not end up in images.
+/// scp-like addresses such as `git@github.com:owner/repo.git` name an account, not a secret, and
+/// are kept as they are.
+pub fn strip_credentials(url: &str) -> String {
+    let Some(scheme_end) = url.find("://").map(|i| i + 3) else {
+        return url.to_string();
+    };
+    let authority_end = url[scheme_end..].find('/').map_or(url.len(), |i| scheme_end + i);
+    match url[scheme_end..authority_end].rfind('@') {
+        Some(at) => format!("{}{}", &url[..scheme_end], &url[scheme_end + at + 1..]),
+        None => url.to_string(),
+    }
+}
+
+/// Last commit and churn within `window` (in git's date syntax, e.g. `2 weeks`) of each of
+/// `files`, given as absolute paths below the canonical work tree, in the history of `rev`.
+/// Files git has never seen are left out.
+pub fn file_history(dir: &Path, files: &HashSet<PathBuf>, window: &str, rev: &str) -> Result<HashMap<PathBuf, FileHistory>> {
+    let toplevel = toplevel(dir)?;
+    let format = format!("--format={}%h{}%ad{}%s", RECORD_START, FIELD_SEPARATOR, FIELD_SEPARATOR);
+
+    // Paths are printed relative to the top of the work tree, unquoted
+    let mut churn = HashMap::new();
+    let since = format!("--since={}", window);
+    let log = run(&toplevel, &["-c", "core.quotePath=false", "log", "--format=", "--name-only", &since, rev, "--"])?;
+    for path in log.lines().filter(|line| !line.is_empty()) {
+        *churn.entry(toplevel.join(path)).or_insert(0) += 1;
+    }
+
+    // The full log is only read until every file has been seen, newest commits first
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(&toplevel)
+        .args(["-c", "core.quotePath=false", "log", "--name-only", "--date=short", &format, rev, "--"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| JockeyError::Git(format!("Failed to run git: {}", e)))?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut histories = HashMap::new();
+    let mut commit: Option<(String, String, String)> = None;
+    for line in BufReader::new(stdout).lines() {
+        let line = line.map_err(|e| JockeyError::Git(format!("Failed to read git log: {}", e)))?;
+        if let Some(record) = line.strip_prefix(RECORD_START) {
+            let mut fields = record.splitn(3, FIELD_SEPARATOR).map(str::to_string);
+            commit = Some((
+                fields.next().unwrap_or_default(),
+                fields.next().unwrap_or_default(),
+                fields.next().unwrap_or_default(),
+            ));
+            continue;
+        }
+        let (Some((hash, date, subject)), false) = (&commit, line.is_empty()) else {
+            continue;
+        };
+        let path = toplevel.join(&line);
+        if files.contains(&path) && !histories.contains_key(&path) {
+            let history = FileHistory {
+                commit: hash.clone(),
+                date: date.clone(),
+                subject: subject.clone(),
+                churn: churn.get(&path).copied().unwrap_or(0),
+                window: window.to_string(),
+            };
+            histories.insert(path, history);
+            if histories.len() == files.len() {
+                break;
+            }
+        }
+    }
+    // Stopping early closes the pipe; git exits on its own or is stopped here
+    let _ = child.kill();
+    let _ = child.wait();
+    Ok(histories)
+}
+
+/// The files below a directory in a committed tree, read from git's object database instead of
+/// the working directory
+pub struct Revision {
+    dir: PathBuf,
+    commit: String,
+    /// Blob id and size of every file, keyed by path relative to `dir`
+    files: BTreeMap<PathBuf, (String, u64)>,
+}
+
+impl Revision {
+    /// Lists the files below `dir` at `rev`, a commit, tag or branch. Symbolic links and
+    /// submodules have no contents of their own and are left out.
+    pub fn list(dir: &Path, rev: &str) -> Result<Self> {
+        let commit = run(dir, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", rev)])
+            .map_err(|_| JockeyError::Git(format!("Unknown revision '{}'", rev)))?;
+        // ls-tree lists the directory it runs in, with paths relative to it
+        let listing = run_bytes(dir, &["ls-tree", "-r", "-z", "-l", commit.trim_end()])?;
+        let mut files = BTreeMap::new();
+        for entry in listing.split(|&b| b == 0).filter(|entry| !entry.is_empty()) {
+            let entry = String::from_utf8_lossy(entry);
+            let Some((info, path)) = entry.split_once('\t') else { continue };
+            // <mode> <type> <id> <size>
+            let info = info.split_whitespace().collect::<Vec<_>>();
+            let [mode, "blob", id, size] = info[..] else { continue };
+            if mode == "120000" {
+                continue;
+            }
+            let size = size.parse().unwrap_or(0);
+            files.insert(PathBuf::from(path), (id.to_string(), size));
+        }
+        if files.is_empty() {
+            return Err(JockeyError::Git(format!("No files below '{}' at '{}'", dir.display(), rev)));
+        }
+        Ok(Self { dir: dir.to_path_buf(), commit: commit.trim_end().to_string(), files })
+    }
+
+    /// Paths of the files, relative to the listed directory
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.keys().cloned().collect()
+    }
+
+    /// Size in bytes of the file at `relative`
+    pub fn size(&self, relative: &Path) -> Option<u64> {
+        self.files.get(relative).map(|(_, size)| *size)
+    }
+
+    /// Contents of a file outside the listed directory, such as `../.gitattributes`, if the
+    /// revision has it
+    pub fn read_outside(&self, relative: &Path) -> Option<Vec<u8>> {
+        // `<commit>:./<path>` is resolved against the directory git runs in
+        let object = format!("{}:./{}", self.commit, relative.to_string_lossy());
+        run_bytes(&self.dir, &["cat-file", "blob", &object]).ok()
+    }
+
+    /// Contents of the files at `relative` paths, in the same order, read in a single
+    /// `git cat-file --batch`.
+    pub fn read(&self, relative: &[&Path]) -> Result<Vec<Vec<u8>>> {
+        let ids = relative
+            .iter()
+            .map(|path| {
+                self.files.get(*path).map(|(id, _)| id.clone()).ok_or_else(|| {
+                    JockeyError::Git(format!("'{}' is not in the revision", path.display()))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let failed = |e: std::io::Error| JockeyError::Git(format!("Failed to read from git cat-file: {}", e));
+
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| JockeyError::Git(format!("Failed to run git: {}", e)))?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let count = ids.len();
+        // Ids are written from another thread, so neither pipe fills up while git waits on the other
+        let writer = std::thread::spawn(move || {
+            ids.iter().try_for_each(|id| writeln!(stdin, "{}", id))
+        });
+
+        let mut reader = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let mut blobs = Vec::with_capacity(count);
+        for _ in 0..count {
+            // Each object is `<id> blob <size>\n<contents>\n`
+            let mut header = String::new();
+            reader.read_line(&mut header).map_err(failed)?;
+            let size = header
+                .split_whitespace()
+                .nth(2)
+                .and_then(|size| size.parse::<usize>().ok())
+                .ok_or_else(|| JockeyError::Git(format!("Unexpected git cat-file output: '{}'", header.trim_end())))?;
+            let mut blob = vec![0; size + 1];
+            reader.read_exact(&mut blob).map_err(failed)?;
+            blob.pop();
+            blobs.push(blob);
+        }
+        writer
+            .join()
+            .map_err(|_| JockeyError::Git("Failed to write to git cat-file".to_string()))?
+            .map_err(failed)?;
+        let _ = child.wait();
+        Ok(blobs)
+    }
+}
@@ -1,8 +1,21 @@
+pub mod apply;
+pub mod attributes;
+pub mod budget;
 pub mod cli;
 pub mod compression;
+pub mod encoding;
 pub mod error;
+pub mod format;
+pub mod git;
 pub mod output;
+pub mod patch;
 pub mod processor;
+pub mod review;
+pub mod sniff;
+pub mod split;
+pub mod tokens;
 pub mod tree;
+pub mod truncate;
+pub mod unpack;
 
 pub use processor::process; 
\ No newline at end of file
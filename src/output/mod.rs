@@ -1,149 +1,759 @@
 use crate::cli::OutputFormat;
+use crate::encoding;
 use crate::error::{Result, JockeyError};
-use serde::Serialize;
+use crate::tokens::format_count;
+use serde::ser::{Error as _, SerializeSeq, SerializeStruct};
+use serde::{Deserialize, Serialize, Serializer};
 use chrono::Local;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+
+mod parse;
+
+pub use parse::parse_image;
 
 const FOOTER: &str = "\n\n---\n\n> 📸 Generated with [Jockey CLI](https://github.com/saint0x/jockey-cli)\n";
 
-#[derive(Serialize)]
+// Markers written after a file body that does not end with a newline, so parsers can restore it exactly
+const NO_NEWLINE_MD: &str = "*No newline at end of file*";
+const NO_NEWLINE_TXT: &str = "\\ No newline at end of file";
+
+// Text images announce a diff with its line count, like the file header does for the body
+const DIFF_TXT: &str = "Diff";
+
+// Header lines describing the git revision of the image
+const BRANCH_HEADER: &str = "Branch:";
+const COMMIT_HEADER: &str = "Commit:";
+const REMOTE_HEADER: &str = "Remote:";
+const WORKING_TREE_HEADER: &str = "Working tree:";
+
+/// Caption for files whose bodies were cut down to their declarations to fit a token budget
+const OUTLINE_NOTE: &str = "Outline only: declarations kept, bodies omitted to fit the token budget";
+
+/// Caption for files cut down to their first and last lines
+const TRUNCATED_NOTE: &str = "Truncated: only the first and last lines are shown";
+
+/// Caption prefix for pieces of a file that was split across image parts
+const CHUNK_NOTE: &str = "Chunk";
+
+/// Caption prefix for files that were decoded from a legacy encoding
+const ENCODING_NOTE: &str = "Converted to UTF-8 from";
+
+/// Caption prefix for the git history of a file
+const HISTORY_NOTE: &str = "History:";
+
+/// Caption for changed files in review images, whose diff precedes the content
+const DIFF_NOTE: &str = "Changed: the diff against the review base comes first, then the file after the change";
+
+/// Caption for files deleted since the base of a review image, which have only a diff
+const DELETED_NOTE: &str = "Deleted: removed since the review base; only the diff is shown";
+
+/// Caption for unchanged files included in review images for context
+const RELATED_NOTE: &str = "Related: unchanged, included for context";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct FileEntry {
     pub path: String,
     pub content: String,
+    #[serde(default)]
+    pub tokens: usize,
+    /// Whether `content` is only an outline of the file rather than the file itself
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub outlined: bool,
+    /// Whether the middle of the file was left out because it exceeded the per-file limits
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub truncated: bool,
+    /// Set when the file was too large for one image part and `content` is only a piece of it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<FileChunk>,
+    /// Original encoding of the file when it was not UTF-8, so it can be written back the same way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Unified diff of the file against the base of a review image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    /// Whether the file was deleted since the base of a review image, so it has no content
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub deleted: bool,
+    /// Whether the file is unchanged and only included in a review image for context
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub related: bool,
+    /// Last commit touching the file and how often it changed recently
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<FileHistory>,
+    /// Where to read `content` from when the image is written, if it is not held in memory
+    #[serde(skip)]
+    pub source: Option<FileSource>,
 }
 
-#[derive(Serialize)]
+/// Git history of a single file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileHistory {
+    /// Abbreviated hash of the last commit touching the file
+    pub commit: String,
+    /// Author date of that commit, as YYYY-MM-DD
+    pub date: String,
+    pub subject: String,
+    /// Number of commits touching the file within `window`
+    pub churn: usize,
+    /// Period the churn was counted over, in git's date syntax, e.g. `1 month`
+    pub window: String,
+}
+
+impl fmt::Display for FileHistory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let noun = if self.churn == 1 { "commit" } else { "commits" };
+        write!(
+            f,
+            "{} {} {} in the last {}; last {} on {}: {}",
+            HISTORY_NOTE, self.churn, noun, self.window, self.commit, self.date, self.subject
+        )
+    }
+}
+
+/// Location of file contents that stay on disk until the image is written
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSource {
+    pub path: PathBuf,
+    /// Size of the whole file in bytes
+    pub size: usize,
+    /// Byte range of the file to use, for pieces of a file split across image parts
+    pub range: Option<Range<usize>>,
+}
+
+/// Position of a piece of a file within the full file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FileChunk {
+    pub index: usize,
+    pub count: usize,
+}
+
+/// Position of an image within a set of split images
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ImagePart {
+    pub index: usize,
+    pub count: usize,
+}
+
+/// A file left out of the image because it did not fit the token budget
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OmittedFile {
+    pub path: String,
+    pub tokens: usize,
+}
+
+/// Why a file listed in the tree has no entry in the image
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    Binary,
+    #[serde(rename = "non-utf-8")]
+    NonUtf8,
+    PermissionDenied,
+    TooLarge,
+    Unreadable,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SkipReason::Binary => "binary",
+            SkipReason::NonUtf8 => "non-UTF-8",
+            SkipReason::PermissionDenied => "permission denied",
+            SkipReason::TooLarge => "too large",
+            SkipReason::Unreadable => "unreadable",
+        })
+    }
+}
+
+/// A file listed in the tree whose contents could not be included
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+/// Revision of the git repository an image was taken from
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GitMetadata {
+    /// Current branch, or `None` on a detached HEAD
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    pub commit: String,
+    /// First line of the HEAD commit message
+    #[serde(default)]
+    pub subject: String,
+    /// URL of the `origin` remote (or the first remote), without credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// Whether the working tree had uncommitted changes, including untracked files. `None` when
+    /// the image was read from a revision rather than the working tree.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dirty: Option<bool>,
+    /// Number of files with uncommitted changes, alongside `dirty`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<usize>,
+}
+
+impl GitMetadata {
+    fn working_tree(&self) -> Option<String> {
+        let dirty = self.dirty?;
+        let modified = self.modified.unwrap_or(0);
+        Some(if dirty {
+            let noun = if modified == 1 { "file" } else { "files" };
+            format!("dirty, {} modified {}", modified, noun)
+        } else {
+            "clean".to_string()
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Repository {
     pub tree: String,
+    /// Total token count of all file contents
+    #[serde(default)]
+    pub tokens: usize,
     pub files: Vec<FileEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub omitted: Vec<OmittedFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part: Option<ImagePart>,
+    /// Written in the image header, next to the timestamp, rather than with the files
+    #[serde(skip)]
+    pub git: Option<GitMetadata>,
+}
+
+impl FileEntry {
+    pub fn new(path: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            content: content.into(),
+            tokens: 0,
+            outlined: false,
+            truncated: false,
+            chunk: None,
+            encoding: None,
+            diff: None,
+            deleted: false,
+            related: false,
+            history: None,
+            source: None,
+        }
+    }
+
+    /// Creates an entry whose contents are read from `source` only when the image is written.
+    pub fn on_disk(path: impl Into<String>, source: impl Into<PathBuf>, size: usize) -> Self {
+        let mut entry = Self::new(path, String::new());
+        entry.source = Some(FileSource { path: source.into(), size, range: None });
+        entry
+    }
+
+    /// Size of the contents in bytes, without loading them
+    pub fn size(&self) -> usize {
+        match &self.source {
+            Some(source) => source.range.as_ref().map_or(source.size, |range| range.len()),
+            None => self.content.len(),
+        }
+    }
+
+    /// Returns the entry with its contents in memory, reading them from disk if needed.
+    pub fn load(&self) -> Result<Cow<'_, FileEntry>> {
+        let Some(source) = &self.source else {
+            return Ok(Cow::Borrowed(self));
+        };
+        let bytes = std::fs::read(&source.path).map_err(|e| {
+            JockeyError::Processing(format!("Failed to read '{}': {}", source.path.display(), e))
+        })?;
+        let content = encoding::decode(&bytes, self.encoding.as_deref()).map_err(|e| {
+            JockeyError::Processing(format!("Failed to read '{}': {}", source.path.display(), e))
+        })?;
+        let content = match &source.range {
+            Some(range) => content
+                .get(range.clone())
+                .ok_or_else(|| {
+                    JockeyError::Processing(format!("'{}' changed while the image was being written", source.path.display()))
+                })?
+                .to_string(),
+            None => content,
+        };
+        Ok(Cow::Owned(FileEntry {
+            path: self.path.clone(),
+            content,
+            tokens: self.tokens,
+            outlined: self.outlined,
+            truncated: self.truncated,
+            chunk: self.chunk,
+            encoding: self.encoding.clone(),
+            diff: self.diff.clone(),
+            deleted: self.deleted,
+            related: self.related,
+            history: self.history.clone(),
+            source: None,
+        }))
+    }
+
+    /// Whether `content` is the whole file, so it is safe to write back to disk
+    pub fn is_complete(&self) -> bool {
+        !self.outlined && !self.truncated && self.chunk.is_none() && !self.deleted
+    }
+
+    fn notes(&self) -> Vec<String> {
+        let mut notes = Vec::new();
+        if self.outlined {
+            notes.push(OUTLINE_NOTE.to_string());
+        }
+        if self.truncated {
+            notes.push(TRUNCATED_NOTE.to_string());
+        }
+        if let Some(chunk) = self.chunk {
+            notes.push(format!("{} {} of {}: the rest of this file is in the other parts", CHUNK_NOTE, chunk.index, chunk.count));
+        }
+        if let Some(encoding) = &self.encoding {
+            notes.push(format!("{} {}", ENCODING_NOTE, encoding));
+        }
+        if let Some(history) = &self.history {
+            notes.push(history.to_string());
+        }
+        if self.deleted {
+            notes.push(DELETED_NOTE.to_string());
+        } else if self.diff.is_some() {
+            notes.push(DIFF_NOTE.to_string());
+        }
+        if self.related {
+            notes.push(RELATED_NOTE.to_string());
+        }
+        notes
+    }
+}
+
+impl Repository {
+    pub fn new(tree: impl Into<String>, files: Vec<FileEntry>) -> Self {
+        Self {
+            tree: tree.into(),
+            tokens: files.iter().map(|f| f.tokens).sum(),
+            files,
+            omitted: Vec::new(),
+            skipped: Vec::new(),
+            part: None,
+            git: None,
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// The part of a JSON or YAML image the parsers need; the timestamp and footer are ignored
+#[derive(Deserialize)]
+struct JockeyImage {
+    #[serde(default)]
+    git: Option<GitMetadata>,
+    repository: Repository,
+}
+
+impl JockeyImage {
+    fn into_repository(self) -> Repository {
+        let mut repo = self.repository;
+        repo.git = self.git;
+        repo
+    }
+}
+
+/// Serializes like `JockeyImage`, but loads file contents one at a time while writing
+#[derive(Serialize)]
+struct StreamedImage<'a> {
+    timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git: Option<&'a GitMetadata>,
+    repository: StreamedRepository<'a>,
+    footer: &'static str,
+}
+
+struct StreamedRepository<'a>(&'a Repository);
+
+struct StreamedFiles<'a>(&'a [FileEntry]);
+
+impl Serialize for StreamedRepository<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        // Mirrors the field order and skip rules of `Repository`
+        let repo = self.0;
+        let mut state = serializer.serialize_struct("Repository", 6)?;
+        state.serialize_field("tree", &repo.tree)?;
+        state.serialize_field("tokens", &repo.tokens)?;
+        state.serialize_field("files", &StreamedFiles(&repo.files))?;
+        if repo.omitted.is_empty() {
+            state.skip_field("omitted")?;
+        } else {
+            state.serialize_field("omitted", &repo.omitted)?;
+        }
+        if repo.skipped.is_empty() {
+            state.skip_field("skipped")?;
+        } else {
+            state.serialize_field("skipped", &repo.skipped)?;
+        }
+        match &repo.part {
+            Some(part) => state.serialize_field("part", part)?,
+            None => state.skip_field("part")?,
+        }
+        state.end()
+    }
 }
 
+impl Serialize for StreamedFiles<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for entry in self.0 {
+            let file = entry.load().map_err(S::Error::custom)?;
+            seq.serialize_element(file.as_ref())?;
+        }
+        seq.end()
+    }
+}
+
+fn timestamp() -> String {
+    let now = Local::now();
+    format!("{} at {}", now.format("%m-%d-%Y"), now.format("%H:%M:%S"))
+}
+
+/// Renders an image into a string. Prefer `write_output` for anything large.
 pub fn format_output(repo: Repository, format: OutputFormat) -> Result<String> {
+    let mut buffer = Vec::new();
+    write_output(&mut buffer, &repo, format)?;
+    String::from_utf8(buffer).map_err(|e| {
+        JockeyError::InvalidFormat(format!("Output is not valid UTF-8: {}", e))
+    })
+}
+
+/// Writes an image to `writer`. Entries whose contents are still on disk are read one at a time
+/// as they are written, so at most one file is held in memory.
+pub fn write_output<W: Write>(writer: &mut W, repo: &Repository, format: OutputFormat) -> Result<()> {
     match format {
-        OutputFormat::Md => format_markdown(repo),
-        OutputFormat::Txt => format_text(repo),
-        OutputFormat::Json => format_json(repo),
-        OutputFormat::Yaml => format_yaml(repo),
-    }
-}
-
-fn format_markdown(repo: Repository) -> Result<String> {
-    let timestamp = format!("{} at {}", 
-        Local::now().format("%m-%d-%Y"),
-        Local::now().format("%H:%M:%S")
-    );
-    let mut output = String::new();
-    output.push_str("# Jockey Image\n\n");
-    output.push_str(&format!("Generated: {}\n\n", timestamp));
-    output.push_str("## Repository Structure\n\n");
-    output.push_str("```\n");
-    output.push_str(&repo.tree);
-    output.push_str("```\n\n");
-
-    for file in repo.files {
-        output.push_str(&format!("## File: {}\n\n", file.path));
-        
+        OutputFormat::Md => write_markdown(writer, repo),
+        OutputFormat::Txt => write_text(writer, repo),
+        OutputFormat::Json => write_json(writer, repo),
+        OutputFormat::Yaml => write_yaml(writer, repo),
+        OutputFormat::Xml => write_xml(writer, repo),
+    }
+}
+
+/// Writes the git metadata lines of the markdown and text headers.
+fn write_git_header<W: Write>(out: &mut W, git: &GitMetadata) -> Result<()> {
+    if let Some(branch) = &git.branch {
+        write!(out, "{} {}\n\n", BRANCH_HEADER, branch)?;
+    }
+    write!(out, "{} {} {}\n\n", COMMIT_HEADER, git.commit, git.subject)?;
+    if let Some(remote) = &git.remote {
+        write!(out, "{} {}\n\n", REMOTE_HEADER, remote)?;
+    }
+    if let Some(state) = git.working_tree() {
+        write!(out, "{} {}\n\n", WORKING_TREE_HEADER, state)?;
+    }
+    Ok(())
+}
+
+fn write_markdown<W: Write>(out: &mut W, repo: &Repository) -> Result<()> {
+    write!(out, "# Jockey Image\n\n")?;
+    write!(out, "Generated: {}\n\n", timestamp())?;
+    if let Some(git) = &repo.git {
+        write_git_header(out, git)?;
+    }
+    if let Some(part) = repo.part {
+        write!(out, "Part: {} of {}\n\n", part.index, part.count)?;
+    }
+    write!(out, "Tokens: {}\n\n", format_count(repo.tokens))?;
+    write!(out, "## Repository Structure\n\n")?;
+    let tree_fence = code_fence(&repo.tree);
+    write!(out, "{}\n{}{}\n\n", tree_fence, repo.tree, tree_fence)?;
+
+    for entry in &repo.files {
+        let file = entry.load()?;
+        write!(out, "## File: {}\n\n", file.path)?;
+        for note in file.notes() {
+            write!(out, "_{}_\n\n", note)?;
+        }
+        if let Some(diff) = &file.diff {
+            let fence = code_fence(diff);
+            writeln!(out, "{}diff", fence)?;
+            write_terminated(out, diff)?;
+            write!(out, "{}\n\n", fence)?;
+        }
+
         // Determine the language for syntax highlighting
         let extension = std::path::Path::new(&file.path)
             .extension()
             .and_then(|ext| ext.to_str())
             .unwrap_or("");
         
-        // Use an extra backtick if the content contains triple backticks
-        let (start_block, end_block) = if file.content.contains("```") {
-            ("````", "````")
-        } else {
-            ("```", "```")
-        };
+        // The fence is always longer than any backtick run inside the content
+        let fence = code_fence(&file.content);
 
         // Add language hint for syntax highlighting
-        if !extension.is_empty() {
-            output.push_str(&format!("{}{}\n", start_block, extension));
-        } else {
-            output.push_str(start_block);
-            output.push('\n');
+        writeln!(out, "{}{}", fence, extension)?;
+
+        // Content is written verbatim; a missing final newline is recorded after the fence
+        let terminated = write_terminated(out, &file.content)?;
+
+        writeln!(out, "{}", fence)?;
+        if !terminated {
+            writeln!(out, "{}", NO_NEWLINE_MD)?;
         }
+        writeln!(out)?;
+    }
 
-        // Fix line wrapping by ensuring each line ends with a newline
-        for line in file.content.lines() {
-            output.push_str(line);
-            output.push('\n');
+    if !repo.omitted.is_empty() {
+        write!(out, "## Omitted Files\n\n")?;
+        write!(out, "These files did not fit in the token budget:\n\n")?;
+        for omitted in &repo.omitted {
+            writeln!(out, "- {} ({} tokens)", omitted.path, format_count(omitted.tokens))?;
+        }
+    }
+
+    if !repo.skipped.is_empty() {
+        write!(out, "## Skipped Files\n\n")?;
+        write!(out, "These files are in the tree but their contents could not be included:\n\n")?;
+        for skipped in &repo.skipped {
+            writeln!(out, "- {} ({})", skipped.path, skipped.reason)?;
         }
-        
-        output.push_str(end_block);
-        output.push_str("\n\n");
     }
 
-    output.push_str(FOOTER);
-    Ok(output)
+    out.write_all(FOOTER.as_bytes())?;
+    Ok(())
 }
 
-fn format_text(repo: Repository) -> Result<String> {
-    let timestamp = format!("{} at {}", 
-        Local::now().format("%m-%d-%Y"),
-        Local::now().format("%H:%M:%S")
-    );
-    let mut output = String::new();
-    output.push_str("Jockey Image\n============\n\n");
-    output.push_str(&format!("Generated: {}\n\n", timestamp));
-    output.push_str("Repository Structure:\n\n");
-    output.push_str(&repo.tree);
-    output.push_str("\n");
+fn write_text<W: Write>(out: &mut W, repo: &Repository) -> Result<()> {
+    write!(out, "Jockey Image\n============\n\n")?;
+    write!(out, "Generated: {}\n\n", timestamp())?;
+    if let Some(git) = &repo.git {
+        write_git_header(out, git)?;
+    }
+    if let Some(part) = repo.part {
+        write!(out, "Part: {} of {}\n\n", part.index, part.count)?;
+    }
+    write!(out, "Tokens: {}\n\n", format_count(repo.tokens))?;
+    write!(out, "Repository Structure:\n\n")?;
+    writeln!(out, "{}", repo.tree)?;
 
-    for file in repo.files {
-        output.push_str(&format!("File: {}\n", file.path));
-        output.push_str("----------------------------------------\n");
-        // Fix line wrapping
-        for line in file.content.lines() {
-            output.push_str(line);
-            output.push('\n');
+    for entry in &repo.files {
+        let file = entry.load()?;
+        // The line count lets parsers find the end of the body without a delimiter
+        let line_count = file.content.split_inclusive('\n').count();
+        writeln!(out, "File: {} ({} lines)", file.path, line_count)?;
+        for note in file.notes() {
+            writeln!(out, "{}", note)?;
+        }
+        if let Some(diff) = &file.diff {
+            writeln!(out, "{} ({} lines):", DIFF_TXT, diff.split_inclusive('\n').count())?;
+            writeln!(out, "----------------------------------------")?;
+            write_terminated(out, diff)?;
+        }
+        writeln!(out, "----------------------------------------")?;
+        if !write_terminated(out, &file.content)? {
+            writeln!(out, "{}", NO_NEWLINE_TXT)?;
         }
-        output.push_str("\n");
+        writeln!(out)?;
     }
 
-    output.push_str(&FOOTER.replace("```", ""));
-    Ok(output)
-}
+    if !repo.omitted.is_empty() {
+        write!(out, "Omitted Files (did not fit in the token budget):\n\n")?;
+        for omitted in &repo.omitted {
+            writeln!(out, "- {} ({} tokens)", omitted.path, format_count(omitted.tokens))?;
+        }
+    }
 
-fn format_json(repo: Repository) -> Result<String> {
-    #[derive(Serialize)]
-    struct JockeyImage {
-        timestamp: String,
-        repository: Repository,
-        footer: String,
+    if !repo.skipped.is_empty() {
+        write!(out, "Skipped Files (in the tree, contents could not be included):\n\n")?;
+        for skipped in &repo.skipped {
+            writeln!(out, "- {} ({})", skipped.path, skipped.reason)?;
+        }
     }
 
-    let image = JockeyImage {
-        timestamp: format!("{} at {}", 
-            Local::now().format("%m-%d-%Y"),
-            Local::now().format("%H:%M:%S")
-        ),
-        repository: repo,
-        footer: "📸 Generated with Jockey CLI (github.com/saint0x/jockey-cli)".to_string(),
+    out.write_all(FOOTER.replace("```", "").as_bytes())?;
+    Ok(())
+}
+
+fn write_json<W: Write>(out: &mut W, repo: &Repository) -> Result<()> {
+    let image = StreamedImage {
+        timestamp: timestamp(),
+        git: repo.git.as_ref(),
+        repository: StreamedRepository(repo),
+        footer: "📸 Generated with Jockey CLI (github.com/saint0x/jockey-cli)",
     };
 
-    serde_json::to_string_pretty(&image).map_err(|e| {
+    serde_json::to_writer_pretty(out, &image).map_err(|e| {
         JockeyError::InvalidFormat(format!("Failed to serialize to JSON: {}", e))
     })
 }
 
-fn format_yaml(repo: Repository) -> Result<String> {
-    #[derive(Serialize)]
-    struct JockeyImage {
-        timestamp: String,
-        repository: Repository,
-        footer: String,
-    }
-
-    let image = JockeyImage {
-        timestamp: format!("{} at {}", 
-            Local::now().format("%m-%d-%Y"),
-            Local::now().format("%H:%M:%S")
-        ),
-        repository: repo,
-        footer: "📸 Generated for free with Jockey CLI (github.com/saint0x/jockey-cli)".to_string(),
+fn write_yaml<W: Write>(out: &mut W, repo: &Repository) -> Result<()> {
+    let image = StreamedImage {
+        timestamp: timestamp(),
+        git: repo.git.as_ref(),
+        repository: StreamedRepository(repo),
+        footer: "📸 Generated for free with Jockey CLI (github.com/saint0x/jockey-cli)",
     };
 
-    serde_yaml::to_string(&image).map_err(|e| {
+    serde_yaml::to_writer(out, &image).map_err(|e| {
         JockeyError::InvalidFormat(format!("Failed to serialize to YAML: {}", e))
     })
-} 
\ No newline at end of file
+}
+
+fn write_xml<W: Write>(out: &mut W, repo: &Repository) -> Result<()> {
+    let part = match repo.part {
+        Some(part) => format!(" part=\"{}\" parts=\"{}\"", part.index, part.count),
+        None => String::new(),
+    };
+    writeln!(
+        out,
+        "<jockey_image generated=\"{}\" tokens=\"{}\"{}>",
+        xml_escape(&timestamp()),
+        repo.tokens,
+        part
+    )?;
+    if let Some(git) = &repo.git {
+        let mut attributes = String::new();
+        if let Some(branch) = &git.branch {
+            attributes.push_str(&format!(" branch=\"{}\"", xml_escape(branch)));
+        }
+        attributes.push_str(&format!(" commit=\"{}\"", xml_escape(&git.commit)));
+        if let Some(remote) = &git.remote {
+            attributes.push_str(&format!(" remote=\"{}\"", xml_escape(remote)));
+        }
+        if let Some(dirty) = git.dirty {
+            attributes.push_str(&format!(" dirty=\"{}\" modified=\"{}\"", dirty, git.modified.unwrap_or(0)));
+        }
+        writeln!(out, "<git{}>{}</git>", attributes, xml_escape(&git.subject))?;
+    }
+    writeln!(out, "<repository_structure>{}</repository_structure>", cdata(&repo.tree))?;
+    writeln!(out, "<documents>")?;
+
+    for (index, entry) in repo.files.iter().enumerate() {
+        let file = entry.load()?;
+        let mut attributes = String::new();
+        if file.outlined {
+            attributes.push_str(" outline=\"true\"");
+        }
+        if file.truncated {
+            attributes.push_str(" truncated=\"true\"");
+        }
+        if let Some(chunk) = file.chunk {
+            attributes.push_str(&format!(" chunk=\"{}\" chunks=\"{}\"", chunk.index, chunk.count));
+        }
+        if let Some(encoding) = &file.encoding {
+            attributes.push_str(&format!(" encoding=\"{}\"", xml_escape(encoding)));
+        }
+        if file.deleted {
+            attributes.push_str(" deleted=\"true\"");
+        }
+        if file.related {
+            attributes.push_str(" related=\"true\"");
+        }
+        writeln!(
+            out,
+            "<document index=\"{}\" tokens=\"{}\"{}>",
+            index + 1,
+            file.tokens,
+            attributes
+        )?;
+        writeln!(out, "<source>{}</source>", xml_escape(&file.path))?;
+        if let Some(history) = &file.history {
+            writeln!(
+                out,
+                "<history commit=\"{}\" date=\"{}\" churn=\"{}\" window=\"{}\">{}</history>",
+                xml_escape(&history.commit),
+                xml_escape(&history.date),
+                history.churn,
+                xml_escape(&history.window),
+                xml_escape(&history.subject)
+            )?;
+        }
+        if let Some(diff) = &file.diff {
+            writeln!(out, "<diff>{}</diff>", cdata(diff))?;
+        }
+        // Content goes in verbatim so the image stays lossless
+        writeln!(out, "<document_content>{}</document_content>", cdata(&file.content))?;
+        writeln!(out, "</document>")?;
+    }
+
+    writeln!(out, "</documents>")?;
+    if !repo.omitted.is_empty() {
+        writeln!(out, "<omitted_files>")?;
+        for omitted in &repo.omitted {
+            writeln!(
+                out,
+                "<file tokens=\"{}\">{}</file>",
+                omitted.tokens,
+                xml_escape(&omitted.path)
+            )?;
+        }
+        writeln!(out, "</omitted_files>")?;
+    }
+    if !repo.skipped.is_empty() {
+        writeln!(out, "<skipped_files>")?;
+        for skipped in &repo.skipped {
+            writeln!(
+                out,
+                "<file reason=\"{}\">{}</file>",
+                xml_escape(&skipped.reason.to_string()),
+                xml_escape(&skipped.path)
+            )?;
+        }
+        writeln!(out, "</skipped_files>")?;
+    }
+    writeln!(out, "</jockey_image>")?;
+    writeln!(out, "<!-- Generated with Jockey CLI (github.com/saint0x/jockey-cli) -->")?;
+    Ok(())
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Wraps text in a CDATA section, splitting it wherever the text itself contains `]]>`.
+fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// Returns a backtick fence longer than the longest backtick run in `content` (at least three).
+fn code_fence(content: &str) -> String {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in content.chars() {
+        if c == '`' {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    "`".repeat((longest + 1).max(3))
+}
+
+/// Writes `content` and a closing newline if it lacks one. Returns whether the content was
+/// already newline-terminated (empty content counts as terminated).
+fn write_terminated<W: Write>(out: &mut W, content: &str) -> std::io::Result<bool> {
+    out.write_all(content.as_bytes())?;
+    if content.is_empty() || content.ends_with('\n') {
+        Ok(true)
+    } else {
+        out.write_all(b"\n")?;
+        Ok(false)
+    }
+}
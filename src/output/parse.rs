@@ -0,0 +1,509 @@
+use super::{FileChunk, FileEntry, FileHistory, GitMetadata, JockeyImage, Repository, BRANCH_HEADER, CHUNK_NOTE, COMMIT_HEADER, DELETED_NOTE, REMOTE_HEADER, WORKING_TREE_HEADER, DIFF_NOTE, DIFF_TXT, ENCODING_NOTE, HISTORY_NOTE, NO_NEWLINE_MD, NO_NEWLINE_TXT, OUTLINE_NOTE, RELATED_NOTE, TRUNCATED_NOTE};
+use crate::cli::OutputFormat;
+use crate::error::{Result, JockeyError};
+use std::path::{Component, Path, PathBuf};
+
+const CDATA_START: &str = "<![CDATA[";
+const CDATA_END: &str = "]]>";
+
+/// Guesses the format of an image from its leading content.
+pub fn detect_format(input: &str) -> OutputFormat {
+    let trimmed = input.trim_start();
+    if trimmed.starts_with('{') {
+        OutputFormat::Json
+    } else if trimmed.starts_with('<') {
+        OutputFormat::Xml
+    } else if trimmed.starts_with("# Jockey Image") {
+        OutputFormat::Md
+    } else if trimmed.starts_with("Jockey Image") {
+        OutputFormat::Txt
+    } else {
+        OutputFormat::Yaml
+    }
+}
+
+/// Parses an image produced by `format_output` back into a `Repository`.
+pub fn parse_image(input: &str) -> Result<Repository> {
+    match detect_format(input) {
+        OutputFormat::Md => parse_markdown(input),
+        OutputFormat::Txt => parse_text(input),
+        OutputFormat::Json => parse_json(input),
+        OutputFormat::Yaml => parse_yaml(input),
+        OutputFormat::Xml => parse_xml(input),
+    }
+}
+
+impl Repository {
+    /// Resolves every file path to a path relative to the processed root.
+    ///
+    /// Absolute paths are made relative to the directory named by the first line of the tree,
+    /// or to their common ancestor when that name does not appear in them. Paths that would
+    /// escape the root are rejected.
+    pub fn relative_paths(&self) -> Result<Vec<PathBuf>> {
+        let paths = self.files.iter().map(|f| PathBuf::from(&f.path)).collect::<Vec<_>>();
+        // The first tree line is the root name, possibly followed by annotations
+        let root_line = self.tree.lines().next().unwrap_or("");
+        let root_name = root_line.split(" (").next().unwrap_or("").trim().trim_end_matches('/');
+        let base = absolute_base(&paths, root_name);
+
+        paths
+            .into_iter()
+            .map(|path| {
+                let relative = match &base {
+                    Some(base) if path.is_absolute() => path.strip_prefix(base).unwrap_or(&path).to_path_buf(),
+                    _ => path,
+                };
+                let escapes = relative.as_os_str().is_empty()
+                    || relative.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+                if escapes {
+                    return Err(JockeyError::InvalidFormat(format!(
+                        "Refusing to use path outside the image root: {}",
+                        relative.display()
+                    )));
+                }
+                Ok(relative)
+            })
+            .collect()
+    }
+}
+
+fn absolute_base(paths: &[PathBuf], root_name: &str) -> Option<PathBuf> {
+    let mut common: Option<PathBuf> = None;
+    for parent in paths.iter().filter(|p| p.is_absolute()).filter_map(|p| p.parent()) {
+        common = Some(match common {
+            None => parent.to_path_buf(),
+            Some(current) => common_ancestor(&current, parent),
+        });
+    }
+    let common = common?;
+
+    // Prefer the processed root named by the tree over the deepest shared directory
+    let mut base = PathBuf::new();
+    let mut root_base = None;
+    for component in common.components() {
+        base.push(component);
+        if !root_name.is_empty() && component.as_os_str() == root_name {
+            root_base = Some(base.clone());
+        }
+    }
+    Some(root_base.unwrap_or(common))
+}
+
+fn common_ancestor(a: &Path, b: &Path) -> PathBuf {
+    a.components()
+        .zip(b.components())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x)
+        .collect()
+}
+
+fn strip_eol(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+fn parse_markdown(input: &str) -> Result<Repository> {
+    let lines = input.split_inclusive('\n').collect::<Vec<_>>();
+    let mut tree = String::new();
+    let mut files = Vec::new();
+    let mut git = GitMetadata::default();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = strip_eol(lines[i]);
+        if tree.is_empty() && read_git_header(&mut git, line) {
+            i += 1;
+        } else if line == "## Repository Structure" {
+            let (body, next) = read_fenced_block(&lines, i + 1)?;
+            tree = body;
+            i = next;
+        } else if let Some(path) = line.strip_prefix("## File: ") {
+            // Italic caption lines may sit between the header and the fence
+            let mut entry = FileEntry::new(path.trim(), String::new());
+            i += 1;
+            while i < lines.len() && !strip_eol(lines[i]).starts_with("```") && !strip_eol(lines[i]).starts_with("## ") {
+                read_note(&mut entry, strip_eol(lines[i]).trim().trim_matches('_'));
+                i += 1;
+            }
+            // Changed files in review images carry a diff block before the content
+            if entry.diff.is_some() {
+                let (diff, next) = read_fenced_block(&lines, i)?;
+                entry.diff = Some(diff);
+                i = next;
+            }
+            let (mut content, next) = read_fenced_block(&lines, i)?;
+            i = next;
+            if lines.get(i).map(|l| strip_eol(l)) == Some(NO_NEWLINE_MD) {
+                content.pop();
+                i += 1;
+            }
+            entry.content = content;
+            files.push(entry);
+        } else {
+            i += 1;
+        }
+    }
+
+    Ok(with_git(Repository::new(tree, files), git))
+}
+
+/// Reads a git metadata line of a markdown or text header into `git`. Returns whether the line
+/// was one.
+fn read_git_header(git: &mut GitMetadata, line: &str) -> bool {
+    let value = |header: &str| line.strip_prefix(header).map(|value| value.trim().to_string());
+    if let Some(branch) = value(BRANCH_HEADER) {
+        git.branch = Some(branch);
+    } else if let Some(commit) = value(COMMIT_HEADER) {
+        let (commit, subject) = commit.split_once(' ').unwrap_or((&commit, ""));
+        git.commit = commit.to_string();
+        git.subject = subject.to_string();
+    } else if let Some(remote) = value(REMOTE_HEADER) {
+        git.remote = Some(remote);
+    } else if let Some(state) = value(WORKING_TREE_HEADER) {
+        git.dirty = Some(state != "clean");
+        git.modified = Some(
+            state
+                .strip_prefix("dirty, ")
+                .and_then(|rest| rest.split(' ').next())
+                .and_then(|count| count.parse().ok())
+                .unwrap_or(0),
+        );
+    } else {
+        return false;
+    }
+    true
+}
+
+/// Attaches the metadata read from a header, if the header had any.
+fn with_git(mut repo: Repository, git: GitMetadata) -> Repository {
+    if !git.commit.is_empty() {
+        repo.git = Some(git);
+    }
+    repo
+}
+
+/// Reads the fenced code block starting at or after `start`, returning its body and the index
+/// of the line after the closing fence.
+fn read_fenced_block(lines: &[&str], start: usize) -> Result<(String, usize)> {
+    let mut i = start;
+    while i < lines.len() && strip_eol(lines[i]).trim().is_empty() {
+        i += 1;
+    }
+
+    let opening = lines.get(i).map(|l| strip_eol(l)).unwrap_or("");
+    let fence_len = opening.chars().take_while(|&c| c == '`').count();
+    if fence_len < 3 {
+        return Err(JockeyError::InvalidFormat(format!(
+            "Expected a code fence on line {}",
+            i + 1
+        )));
+    }
+
+    let mut body = String::new();
+    for (offset, line) in lines[i + 1..].iter().enumerate() {
+        let trimmed = strip_eol(line).trim_end();
+        if trimmed.len() >= fence_len && trimmed.chars().all(|c| c == '`') {
+            return Ok((body, i + offset + 2));
+        }
+        body.push_str(line);
+    }
+
+    Err(JockeyError::InvalidFormat(format!(
+        "Unterminated code block starting on line {}",
+        i + 1
+    )))
+}
+
+fn parse_text(input: &str) -> Result<Repository> {
+    let lines = input.split_inclusive('\n').collect::<Vec<_>>();
+    let mut tree = String::new();
+    let mut files = Vec::new();
+    let mut git = GitMetadata::default();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = strip_eol(lines[i]);
+        if tree.is_empty() && read_git_header(&mut git, line) {
+            i += 1;
+        } else if line == "Repository Structure:" {
+            i += 1;
+            while i < lines.len() && strip_eol(lines[i]).is_empty() {
+                i += 1;
+            }
+            while i < lines.len() && !strip_eol(lines[i]).is_empty() {
+                tree.push_str(lines[i]);
+                i += 1;
+            }
+        } else if let Some(header) = line.strip_prefix("File: ") {
+            let (path, line_count) = header
+                .rsplit_once(" (")
+                .and_then(|(path, count)| {
+                    let count = count.strip_suffix(" lines)")?.parse::<usize>().ok()?;
+                    Some((path, count))
+                })
+                .ok_or_else(|| {
+                    JockeyError::InvalidFormat(format!("Malformed file header on line {}", i + 1))
+                })?;
+
+            // Skip the header, any caption lines and the separator line
+            let mut entry = FileEntry::new(path, String::new());
+            let mut diff_lines = None;
+            let mut body_start = i + 1;
+            while body_start < lines.len() && !strip_eol(lines[body_start]).starts_with("-----") {
+                let note = strip_eol(lines[body_start]);
+                diff_lines = diff_lines.or_else(|| read_diff_header(note));
+                read_note(&mut entry, note);
+                body_start += 1;
+            }
+            body_start += 1;
+            // A diff sits between two separator lines, ahead of the body
+            if let Some(diff_lines) = diff_lines {
+                let diff_end = body_start + diff_lines;
+                if diff_end >= lines.len() {
+                    return Err(JockeyError::InvalidFormat(format!(
+                        "Diff of '{}' is truncated: expected {} lines",
+                        path, diff_lines
+                    )));
+                }
+                entry.diff = Some(lines[body_start..diff_end].concat());
+                body_start = diff_end + 1;
+            }
+            let body_end = body_start + line_count;
+            if body_end > lines.len() {
+                return Err(JockeyError::InvalidFormat(format!(
+                    "File '{}' is truncated: expected {} lines",
+                    path, line_count
+                )));
+            }
+            let mut content = lines[body_start..body_end].concat();
+            i = body_end;
+            if lines.get(i).map(|l| strip_eol(l)) == Some(NO_NEWLINE_TXT) {
+                content.pop();
+                i += 1;
+            }
+            entry.content = content;
+            files.push(entry);
+        } else {
+            i += 1;
+        }
+    }
+
+    Ok(with_git(Repository::new(tree, files), git))
+}
+
+/// Parses the caption written by `FileHistory`'s `Display`, after the `History:` prefix:
+/// `40 commits in the last 1 month; last 1a2b3c4 on 2024-05-01: Fix parser`.
+fn read_history(caption: &str) -> Option<FileHistory> {
+    let (churn, rest) = caption.split_once(' ')?;
+    let rest = rest.strip_prefix("commits ").or_else(|| rest.strip_prefix("commit "))?;
+    let (window, rest) = rest.strip_prefix("in the last ")?.split_once("; last ")?;
+    let (commit, rest) = rest.split_once(" on ")?;
+    let (date, subject) = rest.split_once(": ")?;
+    Some(FileHistory {
+        commit: commit.to_string(),
+        date: date.to_string(),
+        subject: subject.to_string(),
+        churn: churn.parse().ok()?,
+        window: window.to_string(),
+    })
+}
+
+/// Reads the line count from a `Diff (N lines):` line of a text image.
+fn read_diff_header(line: &str) -> Option<usize> {
+    line.strip_prefix(DIFF_TXT)?
+        .strip_prefix(" (")?
+        .strip_suffix(" lines):")?
+        .parse()
+        .ok()
+}
+
+/// Restores the flags described by a caption line written by `FileEntry::notes`.
+fn read_note(entry: &mut FileEntry, note: &str) {
+    if note == OUTLINE_NOTE {
+        entry.outlined = true;
+    } else if note == DIFF_NOTE {
+        // The diff itself follows the captions
+        entry.diff.get_or_insert_with(String::new);
+    } else if note == DELETED_NOTE {
+        entry.deleted = true;
+        entry.diff.get_or_insert_with(String::new);
+    } else if note == RELATED_NOTE {
+        entry.related = true;
+    } else if note == TRUNCATED_NOTE {
+        entry.truncated = true;
+    } else if let Some(rest) = note.strip_prefix(CHUNK_NOTE) {
+        let position = rest.split(':').next().unwrap_or("");
+        if let Some((index, count)) = position.trim().split_once(" of ") {
+            if let (Ok(index), Ok(count)) = (index.parse(), count.parse()) {
+                entry.chunk = Some(FileChunk { index, count });
+            }
+        }
+    } else if let Some(encoding) = note.strip_prefix(ENCODING_NOTE) {
+        entry.encoding = Some(encoding.trim().to_string());
+    } else if let Some(history) = note.strip_prefix(HISTORY_NOTE) {
+        entry.history = read_history(history.trim_start());
+    }
+}
+
+fn parse_json(input: &str) -> Result<Repository> {
+    serde_json::from_str::<JockeyImage>(input)
+        .map(JockeyImage::into_repository)
+        .or_else(|e| serde_json::from_str::<Repository>(input).map_err(|_| e))
+        .map_err(|e| JockeyError::InvalidFormat(format!("Failed to parse JSON image: {}", e)))
+}
+
+fn parse_yaml(input: &str) -> Result<Repository> {
+    serde_yaml::from_str::<JockeyImage>(input)
+        .map(JockeyImage::into_repository)
+        .or_else(|e| serde_yaml::from_str::<Repository>(input).map_err(|_| e))
+        .map_err(|e| JockeyError::InvalidFormat(format!("Failed to parse YAML image: {}", e)))
+}
+
+fn parse_xml(input: &str) -> Result<Repository> {
+    let tree = match element_body(input, "repository_structure") {
+        Some((body, _)) => xml_text(body),
+        None => String::new(),
+    };
+    let git = find_markup(input, "<git ").and_then(|start| {
+        let element = &input[start..];
+        let open_tag = &element[..element.find('>')?];
+        let (subject, _) = element_body(element, "git")?;
+        Some(GitMetadata {
+            branch: attribute(open_tag, "branch").map(xml_unescape),
+            commit: xml_unescape(attribute(open_tag, "commit")?),
+            subject: xml_text(subject),
+            remote: attribute(open_tag, "remote").map(xml_unescape),
+            dirty: attribute(open_tag, "dirty").map(|dirty| dirty == "true"),
+            modified: attribute(open_tag, "modified").and_then(|n| n.parse().ok()),
+        })
+    });
+
+    let mut files = Vec::new();
+    let mut rest = input;
+    while let Some(start) = find_markup(rest, "<document ") {
+        let document = &rest[start..];
+        let open_tag = &document[..document.find('>').unwrap_or(document.len())];
+        let (body, consumed) = element_body(document, "document").ok_or_else(|| {
+            JockeyError::InvalidFormat("Unterminated <document> element".to_string())
+        })?;
+        let path = element_body(body, "source")
+            .map(|(source, _)| xml_text(source))
+            .ok_or_else(|| JockeyError::InvalidFormat("Document without <source>".to_string()))?;
+        let content = element_body(body, "document_content")
+            .map(|(content, _)| xml_text(content))
+            .unwrap_or_default();
+        let mut entry = FileEntry::new(path.trim(), content);
+        entry.outlined = attribute(open_tag, "outline") == Some("true");
+        entry.truncated = attribute(open_tag, "truncated") == Some("true");
+        let chunk = attribute(open_tag, "chunk").and_then(|i| i.parse().ok());
+        let chunks = attribute(open_tag, "chunks").and_then(|n| n.parse().ok());
+        if let (Some(index), Some(count)) = (chunk, chunks) {
+            entry.chunk = Some(FileChunk { index, count });
+        }
+        entry.encoding = attribute(open_tag, "encoding").map(xml_unescape);
+        entry.deleted = attribute(open_tag, "deleted") == Some("true");
+        entry.related = attribute(open_tag, "related") == Some("true");
+        entry.diff = element_body(body, "diff").map(|(diff, _)| xml_text(diff));
+        entry.history = find_markup(body, "<history ").and_then(|start| {
+            let element = &body[start..];
+            let open_tag = &element[..element.find('>')?];
+            let (subject, _) = element_body(element, "history")?;
+            Some(FileHistory {
+                commit: xml_unescape(attribute(open_tag, "commit")?),
+                date: xml_unescape(attribute(open_tag, "date")?),
+                subject: xml_text(subject),
+                churn: attribute(open_tag, "churn")?.parse().ok()?,
+                window: xml_unescape(attribute(open_tag, "window")?),
+            })
+        });
+        files.push(entry);
+        rest = &document[consumed..];
+    }
+
+    let mut repo = Repository::new(tree, files);
+    repo.git = git;
+    Ok(repo)
+}
+
+/// Reads an attribute value from an opening tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let end = start + tag[start..].find('"')?;
+    Some(&tag[start..end])
+}
+
+/// Finds `needle` in `input`, skipping over CDATA sections.
+fn find_markup(input: &str, needle: &str) -> Option<usize> {
+    let mut offset = 0;
+    loop {
+        let rest = &input[offset..];
+        let found = rest.find(needle);
+        let cdata = rest.find(CDATA_START);
+        match (found, cdata) {
+            (Some(f), Some(c)) if c < f => {
+                let end = rest[c..].find(CDATA_END)?;
+                offset += c + end + CDATA_END.len();
+            }
+            (Some(f), _) => return Some(offset + f),
+            (None, _) => return None,
+        }
+    }
+}
+
+/// Returns the body of the first `<tag>` element and the offset just past its closing tag.
+fn element_body<'a>(input: &'a str, tag: &str) -> Option<(&'a str, usize)> {
+    let open = find_markup(input, &format!("<{}", tag))?;
+    let body_start = open + input[open..].find('>')? + 1;
+    let close = format!("</{}>", tag);
+    let body_end = body_start + find_markup(&input[body_start..], &close)?;
+    Some((&input[body_start..body_end], body_end + close.len()))
+}
+
+/// Decodes character data: CDATA sections are taken verbatim and the rest is unescaped.
+fn xml_text(input: &str) -> String {
+    let mut text = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find(CDATA_START) {
+        text.push_str(&xml_unescape(&rest[..start]));
+        let body = &rest[start + CDATA_START.len()..];
+        let end = body.find(CDATA_END).unwrap_or(body.len());
+        text.push_str(&body[..end]);
+        rest = &body[(end + CDATA_END.len()).min(body.len())..];
+    }
+    text.push_str(&xml_unescape(rest));
+    text
+}
+
+fn xml_unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        unescaped.push_str(&rest[..amp]);
+        let entity_end = rest[amp..].find(';').map(|end| amp + end);
+        let decoded = entity_end.and_then(|end| match &rest[amp + 1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse::<u32>()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        });
+        match (decoded, entity_end) {
+            (Some(c), Some(end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[amp + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
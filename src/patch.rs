@@ -0,0 +1,495 @@
+use crate::error::{Result, JockeyError};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use colored::*;
+
+/// How many context lines may be dropped from either end of a hunk when it does not match as is
+const MAX_FUZZ: usize = 2;
+
+#[derive(Debug)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+#[derive(Debug)]
+struct Hunk {
+    header: String,
+    old_start: Option<usize>,
+    lines: Vec<HunkLine>,
+    old_missing_newline: bool,
+    new_missing_newline: bool,
+}
+
+#[derive(Debug)]
+struct FilePatch {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+struct Placement {
+    line: usize,
+    offset: isize,
+    fuzz: usize,
+    /// Whether the context only matched after ignoring whitespace differences
+    whitespace: bool,
+}
+
+/// Applies unified diffs (plain or fenced in a chat response) to the files under `root`.
+pub async fn patch(inputs: &[PathBuf], root: &Path, dry_run: bool) -> Result<()> {
+    let mut patches = Vec::new();
+    for input in inputs {
+        let text = if input.as_os_str() == "-" {
+            let mut text = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut text)?;
+            text
+        } else {
+            fs::read_to_string(input).await.map_err(|e| {
+                JockeyError::Processing(format!("Failed to read patch '{}': {}", input.display(), e))
+            })?
+        };
+        patches.extend(parse_patches(&extract_diff_text(&text))?);
+    }
+
+    if patches.is_empty() {
+        return Err(JockeyError::InvalidFormat("No unified diff found in the input".to_string()));
+    }
+
+    // Every file is patched in memory first; `None` contents delete the file
+    let mut failed_hunks = 0;
+    let mut patched = Vec::new();
+    for file_patch in &patches {
+        let relative = resolve_path(file_patch, root)?;
+        let destination = root.join(&relative);
+        println!("\n{}", relative.display().to_string().bold());
+
+        let original = if file_patch.old_path.is_some() {
+            match fs::read_to_string(&destination).await {
+                Ok(content) => content,
+                Err(e) => {
+                    println!("  {} file could not be read: {}", "✗".red(), e);
+                    failed_hunks += file_patch.hunks.len();
+                    continue;
+                }
+            }
+        } else if destination.exists() {
+            // A diff that creates a file must not silently replace one that is already there
+            println!("  {} file already exists, but the diff creates it", "✗".red());
+            failed_hunks += file_patch.hunks.len();
+            continue;
+        } else {
+            String::new()
+        };
+
+        let mut file = PatchedFile::new(&original);
+        let mut offset = 0isize;
+        let mut cursor = 0usize;
+        let mut file_failed = false;
+        for (index, hunk) in file_patch.hunks.iter().enumerate() {
+            match file.apply(hunk, &mut offset, &mut cursor) {
+                Some(placement) => {
+                    let mut note = format!("applied at line {}", placement.line);
+                    if placement.offset != 0 {
+                        note.push_str(&format!(" (offset {:+})", placement.offset));
+                    }
+                    if placement.fuzz > 0 {
+                        note.push_str(&format!(" (fuzz {})", placement.fuzz));
+                    }
+                    if placement.whitespace {
+                        note.push_str(" (ignoring whitespace)");
+                    }
+                    println!("  {} hunk {} {} {}", "✓".green(), index + 1, hunk.header.cyan(), note);
+                }
+                None => {
+                    file_failed = true;
+                    failed_hunks += 1;
+                    println!("  {} hunk {} {} failed: context not found", "✗".red(), index + 1, hunk.header.cyan());
+                }
+            }
+        }
+
+        if !file_failed {
+            patched.push((destination, file_patch.new_path.is_some().then(|| file.render())));
+        }
+    }
+
+    // Nothing is written unless every hunk of every file applied, so a failed patch never
+    // leaves the tree half-edited
+    if failed_hunks > 0 {
+        return Err(JockeyError::Processing(format!(
+            "{} hunk(s) failed to apply; no files were changed",
+            failed_hunks
+        )));
+    }
+
+    if !dry_run {
+        for (destination, content) in &patched {
+            match content {
+                None => fs::remove_file(destination).await.map_err(|e| {
+                    JockeyError::Processing(format!("Failed to delete '{}': {}", destination.display(), e))
+                })?,
+                Some(content) => {
+                    if let Some(parent) = destination.parent() {
+                        fs::create_dir_all(parent).await.map_err(|e| {
+                            JockeyError::Processing(format!("Failed to create directory '{}': {}", parent.display(), e))
+                        })?;
+                    }
+                    fs::write(destination, content).await.map_err(|e| {
+                        JockeyError::Processing(format!("Failed to write '{}': {}", destination.display(), e))
+                    })?;
+                }
+            }
+        }
+    }
+
+    if dry_run {
+        println!("\n{}", "Dry run: all hunks apply cleanly, nothing was written.".green().bold());
+    } else {
+        println!("\n{}", "Patch applied successfully!".green().bold());
+        println!("{} {}", "Files:".blue(), patched.len().to_string().cyan());
+    }
+    Ok(())
+}
+
+/// Pulls the diffs out of fenced ```diff/```patch blocks, or returns the input when there are none.
+fn extract_diff_text(input: &str) -> String {
+    let mut blocks = String::new();
+    let mut lines = input.lines();
+    while let Some(line) = lines.next() {
+        let fence_len = line.chars().take_while(|&c| c == '`').count();
+        if fence_len < 3 {
+            continue;
+        }
+        let info = line[fence_len..].trim();
+        let mut body = String::new();
+        for line in lines.by_ref() {
+            let trimmed = line.trim_end();
+            if trimmed.len() >= fence_len && trimmed.chars().all(|c| c == '`') {
+                break;
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+        if info == "diff" || info == "patch" || body.lines().any(|l| l.starts_with("@@")) {
+            blocks.push_str(&body);
+        }
+    }
+
+    if blocks.is_empty() {
+        input.to_string()
+    } else {
+        blocks
+    }
+}
+
+fn parse_patches(text: &str) -> Result<Vec<FilePatch>> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        if is_file_header(&lines, i) {
+            patches.push(FilePatch {
+                old_path: header_path(&line[4..]),
+                new_path: header_path(&lines[i + 1][4..]),
+                hunks: Vec::new(),
+            });
+            i += 2;
+        } else if line.starts_with("@@") {
+            let current = patches.last_mut().ok_or_else(|| {
+                JockeyError::InvalidFormat(format!("Hunk on line {} has no file header", i + 1))
+            })?;
+            let (hunk, next) = parse_hunk(&lines, i);
+            current.hunks.push(hunk);
+            i = next;
+        } else {
+            i += 1;
+        }
+    }
+
+    Ok(patches.into_iter().filter(|p| !p.hunks.is_empty()).collect())
+}
+
+fn is_file_header(lines: &[&str], i: usize) -> bool {
+    lines[i].starts_with("--- ") && lines.get(i + 1).is_some_and(|next| next.starts_with("+++ "))
+}
+
+fn header_path(raw: &str) -> Option<String> {
+    // Drop the optional timestamp that follows a tab
+    let path = raw.split('\t').next().unwrap_or(raw).trim();
+    if path == "/dev/null" {
+        None
+    } else {
+        Some(path.to_string())
+    }
+}
+
+/// Parses the hunk starting at `start`. The line counts in the header decide where it ends, so
+/// removed and added lines that look like file headers stay in the hunk. Models often get the
+/// counts wrong, so when they do not fit the lines that follow, the hunk ends at the next header
+/// or non-diff line instead.
+fn parse_hunk(lines: &[&str], start: usize) -> (Hunk, usize) {
+    let header = lines[start];
+    let old_start = header
+        .strip_prefix("@@ -")
+        .and_then(|rest| rest.split([',', ' ']).next())
+        .and_then(|n| n.parse::<usize>().ok());
+    let new_hunk = || Hunk {
+        header: header.trim_end().to_string(),
+        old_start,
+        lines: Vec::new(),
+        old_missing_newline: false,
+        new_missing_newline: false,
+    };
+
+    if let Some((old_count, new_count)) = hunk_counts(header) {
+        let mut hunk = new_hunk();
+        if let Some(next) = parse_counted(&mut hunk, lines, start + 1, old_count, new_count) {
+            return (hunk, next);
+        }
+    }
+
+    let mut hunk = new_hunk();
+    let mut trailing_blank = 0;
+    let mut i = start + 1;
+
+    while i < lines.len() && !lines[i].starts_with("@@") && !is_file_header(lines, i) {
+        let line = lines[i];
+        if line.is_empty() {
+            // Chat clients strip the single space of empty context lines
+            hunk.lines.push(HunkLine::Context(String::new()));
+            trailing_blank += 1;
+        } else if let Some(text) = line.strip_prefix(' ') {
+            hunk.lines.push(HunkLine::Context(text.to_string()));
+            trailing_blank = 0;
+        } else if let Some(text) = line.strip_prefix('-') {
+            hunk.lines.push(HunkLine::Remove(text.to_string()));
+            trailing_blank = 0;
+        } else if let Some(text) = line.strip_prefix('+') {
+            hunk.lines.push(HunkLine::Add(text.to_string()));
+            trailing_blank = 0;
+        } else if line.starts_with('\\') {
+            hunk.mark_missing_newline();
+        } else {
+            break;
+        }
+        i += 1;
+    }
+
+    // Blank lines separating the hunk from whatever follows are not part of it
+    hunk.lines.truncate(hunk.lines.len() - trailing_blank);
+    (hunk, i)
+}
+
+/// Old and new line counts from a `@@ -a,b +c,d @@` header; an omitted count is 1.
+fn hunk_counts(header: &str) -> Option<(usize, usize)> {
+    let mut ranges = header.strip_prefix("@@ ")?.split(' ');
+    let count = |range: &str, sign: char| -> Option<usize> {
+        match range.strip_prefix(sign)?.split_once(',') {
+            Some((_, count)) => count.parse().ok(),
+            None => Some(1),
+        }
+    };
+    Some((count(ranges.next()?, '-')?, count(ranges.next()?, '+')?))
+}
+
+/// Reads exactly `old` old and `new` new lines into `hunk` and returns the line after them, or
+/// `None` when the lines do not add up to the counts.
+fn parse_counted(hunk: &mut Hunk, lines: &[&str], mut i: usize, mut old: usize, mut new: usize) -> Option<usize> {
+    while old > 0 || new > 0 {
+        let line = *lines.get(i)?;
+        let (text, in_old, in_new) = match line.chars().next() {
+            // Chat clients strip the single space of empty context lines
+            None => ("", true, true),
+            Some(' ') => (&line[1..], true, true),
+            Some('-') => (&line[1..], true, false),
+            Some('+') => (&line[1..], false, true),
+            Some('\\') => {
+                hunk.mark_missing_newline();
+                i += 1;
+                continue;
+            }
+            _ => return None,
+        };
+        if (in_old && old == 0) || (in_new && new == 0) {
+            return None;
+        }
+        hunk.lines.push(match (in_old, in_new) {
+            (true, true) => HunkLine::Context(text.to_string()),
+            (true, false) => HunkLine::Remove(text.to_string()),
+            _ => HunkLine::Add(text.to_string()),
+        });
+        old -= usize::from(in_old);
+        new -= usize::from(in_new);
+        i += 1;
+    }
+    while lines.get(i).is_some_and(|line| line.starts_with('\\')) {
+        hunk.mark_missing_newline();
+        i += 1;
+    }
+
+    // More diff lines straight after the counted ones mean the counts are too small
+    let mut next = i;
+    while lines.get(next).is_some_and(|line| line.is_empty()) {
+        next += 1;
+    }
+    let continues = lines.get(next).is_some_and(|line| {
+        line.starts_with([' ', '-', '+']) && !is_file_header(lines, next)
+    });
+    (!continues).then_some(i)
+}
+
+impl Hunk {
+    /// Records a `\ No newline at end of file` marker, which refers to the line before it.
+    fn mark_missing_newline(&mut self) {
+        match self.lines.last() {
+            Some(HunkLine::Remove(_)) => self.old_missing_newline = true,
+            Some(HunkLine::Add(_)) => self.new_missing_newline = true,
+            Some(HunkLine::Context(_)) => {
+                self.old_missing_newline = true;
+                self.new_missing_newline = true;
+            }
+            None => {}
+        }
+    }
+}
+
+fn resolve_path(file_patch: &FilePatch, root: &Path) -> Result<PathBuf> {
+    let raw = file_patch
+        .new_path
+        .as_deref()
+        .or(file_patch.old_path.as_deref())
+        .ok_or_else(|| JockeyError::InvalidFormat("Patch without a file path".to_string()))?;
+
+    // Strip git-style a/ and b/ prefixes unless the prefixed path really exists
+    let git_style = file_patch.old_path.as_deref().is_none_or(|p| p.starts_with("a/"))
+        && file_patch.new_path.as_deref().is_none_or(|p| p.starts_with("b/"));
+    let path = match raw.get(2..) {
+        Some(stripped) if git_style && !root.join(raw).exists() => PathBuf::from(stripped),
+        _ => PathBuf::from(raw),
+    };
+
+    if path.is_absolute() || path.components().any(|c| matches!(c, std::path::Component::ParentDir)) {
+        return Err(JockeyError::InvalidFormat(format!(
+            "Refusing to patch path outside the project root: {}",
+            raw
+        )));
+    }
+    Ok(path)
+}
+
+struct PatchedFile {
+    lines: Vec<String>,
+    line_ending: &'static str,
+    trailing_newline: bool,
+}
+
+impl PatchedFile {
+    fn new(content: &str) -> Self {
+        Self {
+            lines: content.lines().map(str::to_string).collect(),
+            line_ending: if content.contains("\r\n") { "\r\n" } else { "\n" },
+            trailing_newline: content.is_empty() || content.ends_with('\n'),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut output = self.lines.join(self.line_ending);
+        if self.trailing_newline && !self.lines.is_empty() {
+            output.push_str(self.line_ending);
+        }
+        output
+    }
+
+    /// Applies a hunk near its expected position, tolerating whitespace differences and, failing
+    /// that, dropping up to `MAX_FUZZ` context lines from either end.
+    fn apply(&mut self, hunk: &Hunk, offset: &mut isize, cursor: &mut usize) -> Option<Placement> {
+        let leading = hunk.lines.iter().take_while(|l| matches!(l, HunkLine::Context(_))).count();
+        let trailing = hunk.lines.iter().rev().take_while(|l| matches!(l, HunkLine::Context(_))).count();
+
+        for fuzz in 0..=MAX_FUZZ {
+            let skip_front = fuzz.min(leading);
+            let skip_back = fuzz.min(trailing).min(hunk.lines.len() - skip_front);
+            if fuzz > 0 && skip_front == 0 && skip_back == 0 {
+                break;
+            }
+            let lines = &hunk.lines[skip_front..hunk.lines.len() - skip_back];
+            let old = lines.iter().filter_map(|l| match l {
+                HunkLine::Context(t) | HunkLine::Remove(t) => Some(t.as_str()),
+                HunkLine::Add(_) => None,
+            }).collect::<Vec<_>>();
+            let expected = match hunk.old_start {
+                Some(start) => ((start.saturating_sub(1) + skip_front) as isize + *offset).max(0) as usize,
+                None => *cursor,
+            };
+            if let Some((position, exact)) = self.find(&old, expected) {
+                // Context lines keep the file's own text, which may differ in whitespace
+                let mut existing = self.lines[position..position + old.len()].iter();
+                let new = lines.iter().filter_map(|l| match l {
+                    HunkLine::Context(_) => existing.next().cloned(),
+                    HunkLine::Remove(_) => {
+                        existing.next();
+                        None
+                    }
+                    HunkLine::Add(t) => Some(t.clone()),
+                }).collect::<Vec<_>>();
+                self.lines.splice(position..position + old.len(), new.iter().cloned());
+                let applied_offset = position as isize - expected as isize;
+                *offset += applied_offset + new.len() as isize - old.len() as isize;
+                *cursor = position + new.len();
+                if hunk.new_missing_newline {
+                    self.trailing_newline = false;
+                } else if hunk.old_missing_newline {
+                    self.trailing_newline = true;
+                }
+                return Some(Placement {
+                    line: position + 1,
+                    offset: applied_offset,
+                    fuzz,
+                    whitespace: !exact,
+                });
+            }
+        }
+        None
+    }
+
+    /// Finds `old` in the file, searching outward from `expected`, and returns where and whether
+    /// the match was exact; exact matches win over matches that only agree after trimming
+    /// whitespace.
+    fn find(&self, old: &[&str], expected: usize) -> Option<(usize, bool)> {
+        if old.is_empty() {
+            return Some((expected.min(self.lines.len()), true));
+        }
+        if old.len() > self.lines.len() {
+            return None;
+        }
+        let last = self.lines.len() - old.len();
+        let expected = expected.min(last);
+        let comparisons: [fn(&str, &str) -> bool; 3] = [
+            |a, b| a == b,
+            |a, b| a.trim_end() == b.trim_end(),
+            |a, b| a.split_whitespace().eq(b.split_whitespace()),
+        ];
+
+        for (strictness, same) in comparisons.into_iter().enumerate() {
+            let matches_at = |start: usize| {
+                self.lines[start..start + old.len()]
+                    .iter()
+                    .zip(old)
+                    .all(|(line, wanted)| same(line, wanted))
+            };
+            for distance in 0..=last {
+                if distance <= expected && matches_at(expected - distance) {
+                    return Some((expected - distance, strictness == 0));
+                }
+                if expected + distance <= last && matches_at(expected + distance) {
+                    return Some((expected + distance, strictness == 0));
+                }
+            }
+        }
+        None
+    }
+}
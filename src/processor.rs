@@ -1,50 +1,226 @@
+use crate::budget::{self, Candidate};
 use crate::cli::{Commands, Config};
 use crate::error::{Result, JockeyError};
-use crate::output::{FileEntry, Repository, format_output};
-use crate::tree::TreeBuilder;
+use crate::format::format_file_size;
+use crate::git::{self, ChangeSet};
+use crate::output::{FileEntry, FileHistory, Repository, SkipReason, SkippedFile, write_output};
+use crate::sniff;
+use crate::split;
+use crate::tokens::{default_tokenizer, format_count};
+use crate::tree::{FileStats, RenderOptions, TreeBuilder};
+use crate::truncate;
 use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use tokio::fs;
 use chrono::Local;
 use colored::*;
 
 const OUTPUT_DIR: &str = "jockey-img";
 
-fn format_file_size(size: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-
-    if size >= GB {
-        format!("{:.2} GB", size as f64 / GB as f64)
-    } else if size >= MB {
-        format!("{:.2} MB", size as f64 / MB as f64)
-    } else if size >= KB {
-        format!("{:.2} KB", size as f64 / KB as f64)
-    } else {
-        format!("{} bytes", size)
-    }
-}
-
-async fn get_unique_filename(dir: &std::path::Path, base_name: &str, extension: &str) -> Result<String> {
+/// Returns a `<base>_<date>` stem (with a `(n)` counter if needed) such that `stem + suffix`
+/// does not exist yet, where `suffix` is the extension of the first file to be written.
+async fn get_unique_stem(dir: &Path, base_name: &str, suffix: &str) -> Result<String> {
     let mut counter = 0;
-    let mut filename = format!("{}_{}.{}", base_name, Local::now().format("%m-%d-%y"), extension);
+    let mut stem = format!("{}_{}", base_name, Local::now().format("%m-%d-%y"));
     
-    while dir.join(&filename).exists() {
+    while dir.join(format!("{}{}", stem, suffix)).exists() {
         counter += 1;
-        filename = format!("{}_{}{}.{}", 
+        stem = format!("{}_{}({})", 
             base_name, 
             Local::now().format("%m-%d-%y"),
-            format!("({})", counter),
-            extension
+            counter
         );
     }
     
-    Ok(filename)
+    Ok(stem)
+}
+
+/// Output paths for an explicit `--output`; split images become `<stem>.part1.<ext>`, ...
+fn explicit_output_paths(path: &Path, part_count: usize) -> Vec<PathBuf> {
+    if part_count <= 1 {
+        return vec![path.to_path_buf()];
+    }
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (1..=part_count)
+        .map(|index| path.with_file_name(format!("{}.part{}{}", stem, index, extension)))
+        .collect()
+}
+
+/// Output paths under `OUTPUT_DIR`, named after `name` and today's date. Split images are
+/// named `<stem>.part1.md`, `<stem>.part2.md`, ...
+async fn dated_output_paths(root_dir: &Path, name: &str, extension: &str, part_count: usize) -> Result<Vec<PathBuf>> {
+    // Ensure output directory exists
+    let jockey_dir = root_dir.join(OUTPUT_DIR);
+    fs::create_dir_all(&jockey_dir).await.map_err(|e| {
+        JockeyError::Processing(format!("Failed to create {} directory: {}", OUTPUT_DIR, e))
+    })?;
+
+    let suffix = |index: usize| {
+        if part_count > 1 {
+            format!(".part{}.{}", index, extension)
+        } else {
+            format!(".{}", extension)
+        }
+    };
+    let stem = get_unique_stem(&jockey_dir, name, &suffix(1)).await?;
+    Ok((1..=part_count)
+        .map(|index| jockey_dir.join(format!("{}{}", stem, suffix(index))))
+        .collect())
+}
+
+/// Describes skipped files for the summary, e.g. `4 files (3 binary, 1 too large)`.
+fn summarize_skipped(skipped: &[SkippedFile]) -> Option<String> {
+    if skipped.is_empty() {
+        return None;
+    }
+    let mut counts = BTreeMap::new();
+    for file in skipped {
+        *counts.entry(file.reason).or_insert(0) += 1;
+    }
+    let reasons = counts
+        .into_iter()
+        .map(|(reason, count)| format!("{} {}", count, reason))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(format!("{} files ({})", skipped.len().to_string().yellow(), reasons))
+}
+
+/// Prints a line of the summary. With --stdout the image is the only thing on stdout, so the
+/// summary goes to stderr.
+pub(crate) fn report(config: &Config, line: String) {
+    if config.to_stdout() {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Writes the parts of an image to stdout, the --output path or a dated file named after `name`
+/// in `OUTPUT_DIR`, and reports where they went.
+pub(crate) async fn write_image(config: &Config, root_dir: &Path, name: &str, parts: &[Repository]) -> Result<()> {
+    let extension = match config.format() {
+        crate::cli::OutputFormat::Md => "md",
+        crate::cli::OutputFormat::Txt => "txt",
+        crate::cli::OutputFormat::Json => "json",
+        crate::cli::OutputFormat::Yaml => "yaml",
+        crate::cli::OutputFormat::Xml => "xml",
+    };
+
+    let mut locations = Vec::new();
+    if config.to_stdout() {
+        let stdout = std::io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+        for part in parts {
+            write_output(&mut writer, part, config.format())?;
+        }
+        writer.flush().map_err(|e| {
+            JockeyError::Processing(format!("Failed to write to stdout: {}", e))
+        })?;
+        locations.push(("stdout".to_string(), None));
+    } else {
+        let output_paths = match config.output_path() {
+            Some(path) => explicit_output_paths(path, parts.len()),
+            None => dated_output_paths(root_dir, name, extension, parts.len()).await?,
+        };
+        for (part, output_path) in parts.iter().zip(&output_paths) {
+            if let Some(parent) = output_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent).await.map_err(|e| {
+                    JockeyError::Processing(format!("Failed to create directory '{}': {}", parent.display(), e))
+                })?;
+            }
+
+            // Stream the image to disk one file at a time
+            let file = std::fs::File::create(output_path).map_err(|e| {
+                JockeyError::Processing(format!("Failed to create output file '{}': {}", output_path.display(), e))
+            })?;
+            let mut writer = BufWriter::new(file);
+            write_output(&mut writer, part, config.format())?;
+            writer.flush().map_err(|e| {
+                JockeyError::Processing(format!("Failed to write output file: {}", e))
+            })?;
+
+            // Get and format file size
+            let file_size = fs::metadata(output_path).await.map_err(|e| {
+                JockeyError::Processing(format!("Failed to get file size: {}", e))
+            })?.len();
+
+            // Get relative path for display
+            let relative_path = output_path.strip_prefix(root_dir)
+                .unwrap_or(output_path)
+                .to_string_lossy()
+                .into_owned();
+            locations.push((relative_path, Some(file_size)));
+        }
+    }
+
+    let tokenizer = default_tokenizer();
+    report(config, format!("\n{}", "Jockey image created successfully!".green().bold()));
+    for ((location, file_size), part) in locations.into_iter().zip(parts) {
+        let size = file_size.map(|size| format!("{}, ", format_file_size(size).cyan())).unwrap_or_default();
+        report(config, format!("{} {} ({}{} {} tokens)", 
+            "Location:".blue(),
+            location.yellow(),
+            size,
+            format_count(part.tokens).cyan(),
+            tokenizer.name()
+        ));
+    }
+    Ok(())
+}
+
+/// Maps the canonical form of each walked file under `target_dir` back to the walked path. git
+/// reports paths below the canonical work tree, which may differ from the walked ones.
+fn canonical_paths<'a>(target_dir: &Path, files: &'a [PathBuf]) -> Result<HashMap<PathBuf, &'a PathBuf>> {
+    let canonical_dir = std::fs::canonicalize(target_dir).map_err(|e| {
+        JockeyError::Processing(format!("Failed to resolve '{}': {}", target_dir.display(), e))
+    })?;
+    Ok(files
+        .iter()
+        .filter_map(|path| Some((canonical_dir.join(path.strip_prefix(target_dir).ok()?), path)))
+        .collect())
+}
+
+/// The walked files under `target_dir` that are part of `changes`.
+fn changed_paths(target_dir: &Path, files: &[PathBuf], changes: &ChangeSet) -> Result<HashSet<PathBuf>> {
+    let changed = git::changed_files(target_dir, changes)?;
+    Ok(canonical_paths(target_dir, files)?
+        .into_iter()
+        .filter(|(canonical, _)| changed.contains(canonical))
+        .map(|(_, path)| path.clone())
+        .collect())
+}
+
+/// Git history of the walked files under `target_dir` up to `rev`, keyed by walked path.
+fn file_histories(target_dir: &Path, files: &[PathBuf], window: &str, rev: &str) -> Result<HashMap<PathBuf, FileHistory>> {
+    let canonical = canonical_paths(target_dir, files)?;
+    let wanted = canonical.keys().cloned().collect();
+    Ok(git::file_history(target_dir, &wanted, window, rev)?
+        .into_iter()
+        .filter_map(|(path, history)| Some((canonical.get(&path)?.to_path_buf(), history)))
+        .collect())
 }
 
 pub async fn process(config: Config) -> Result<()> {
     match &config.command {
-        Commands::Generate { .. } => generate(&config).await,
+        Commands::Generate(_) => generate(&config).await,
+        Commands::Review { base, no_related, .. } => {
+            crate::review::review(&config, base, !*no_related).await
+        }
+        Commands::Unpack { image, into, force } => {
+            crate::unpack::unpack(image, into.as_deref(), *force).await
+        }
+        Commands::Apply { image, yes, .. } => {
+            crate::apply::apply(image, &config.get_target_dir()?, *yes).await
+        }
+        Commands::Patch { patches, dry_run } => {
+            crate::patch::patch(patches, &Config::get_root_dir()?, *dry_run).await
+        }
     }
 }
 
@@ -53,77 +229,217 @@ async fn generate(config: &Config) -> Result<()> {
     let root_dir = Config::get_root_dir()?;
     let target_dir = config.get_target_dir()?;
     
-    // Build directory tree and collect files
-    let (tree, files) = TreeBuilder::process_directory(&target_dir, config.exclude_patterns())?;
+    // Changes are relative to the working directory, which a revision does not look at
+    let changes = config.changes();
+    if let (Some(changes), Some(rev)) = (&changes, config.revision()) {
+        return Err(JockeyError::Config(format!("Cannot restrict an image of '{}' to {} changes", rev, changes)));
+    }
+
+    // Walk the directory tree and collect files, or list them from the revision's tree; the
+    // tree is rendered once files are measured
+    let revision = config.revision().map(|rev| git::Revision::list(&target_dir, rev)).transpose()?;
+    let tree_root = match &revision {
+        Some(revision) => {
+            let read = |relative: &Path| {
+                let contents = if relative.starts_with("..") {
+                    revision.read_outside(relative)?
+                } else {
+                    revision.read(&[relative]).ok()?.pop()?
+                };
+                Some(String::from_utf8_lossy(&contents).into_owned())
+            };
+            TreeBuilder::from_listing(&target_dir, &revision.paths(), read, config.exclude_patterns(), config.include_patterns(), config.tree_order(), config.include_generated())?
+        }
+        None => TreeBuilder::walk(&target_dir, config.exclude_patterns(), config.include_patterns(), config.tree_order(), config.include_generated())?,
+    };
+    let files = tree_root.files();
+    let marked_count = tree_root.marked_count();
+
+    // Restrict the image to changed files; the tree still shows every file for context
+    let changed = match &changes {
+        Some(changes) => Some(changed_paths(&target_dir, &files, changes)?),
+        None => None,
+    };
+    let in_image = |path: &PathBuf| changed.as_ref().is_none_or(|changed| changed.contains(path));
+    // Unchanged files are only measured for the tree annotations
+    let annotations = config.tree_annotations();
+    let scan = files
+        .iter()
+        .filter(|path| !annotations.is_empty() || in_image(path))
+        .collect::<Vec<_>>();
+
+    let mut histories = match config.git_history() {
+        Some(window) => {
+            let included = files.iter().filter(|path| in_image(path)).cloned().collect::<Vec<_>>();
+            file_histories(&target_dir, &included, window, config.revision().unwrap_or("HEAD"))?
+        }
+        None => HashMap::new(),
+    };
     
-    // Process files in parallel for better performance on large codebases
-    let processed_files = files
-        .par_iter()
-        .filter_map(|path| {
-            // Skip binary files and non-UTF-8 files
-            match std::fs::read_to_string(path) {
-                Ok(content) => Some(Ok(FileEntry {
-                    path: path.to_string_lossy().into_owned(),
-                    content,
-                })),
-                Err(_) => None, // Skip files that can't be read as UTF-8
-            }
+    // Files at a revision are read from git's object database in a single pass, leaving out
+    // those too large to read at all
+    let mut blobs = match &revision {
+        Some(revision) => {
+            let readable = scan
+                .iter()
+                .filter_map(|path| path.strip_prefix(&target_dir).ok())
+                .filter(|relative| revision.size(relative).is_some_and(|size| size <= sniff::MAX_FILE_SIZE))
+                .collect::<Vec<_>>();
+            let contents = revision.read(&readable)?;
+            readable.into_iter().map(|relative| target_dir.join(relative)).zip(contents).collect()
+        }
+        None => HashMap::new(),
+    };
+    let scan = scan.into_iter().map(|path| (path, blobs.remove(path))).collect::<Vec<_>>();
+
+    // Measure files in parallel for better performance on large codebases. Contents are dropped
+    // again right away and streamed from disk when the image is written.
+    let tokenizer = default_tokenizer();
+    let file_limits = config.file_limits();
+    let path_style = config.path_style();
+    let scanned = scan
+        .into_par_iter()
+        .map(|(path, blob)| {
+            let display = path_style.display(path, &target_dir);
+            let (size, text, modified) = match &revision {
+                Some(revision) => {
+                    let size = path.strip_prefix(&target_dir).ok().and_then(|relative| revision.size(relative));
+                    (size.unwrap_or(0), blob.map_or(Err(SkipReason::TooLarge), sniff::decode), None)
+                }
+                None => {
+                    let metadata = std::fs::metadata(path).ok();
+                    let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+                    (metadata.map_or(0, |m| m.len()), sniff::read_text(path), modified)
+                }
+            };
+            let mut stats = FileStats { size, ..FileStats::default() };
+            let scanned = match text {
+                Ok(text) => {
+                    let tokens = tokenizer.count(&text.content);
+                    stats.lines = Some(text.content.lines().count());
+                    stats.tokens = Some(tokens);
+
+                    // Truncated files are small enough to keep in memory instead of re-reading them
+                    let mut entry = match truncate::truncate(&text.content, file_limits, tokenizer) {
+                        Some(truncated) => {
+                            let mut entry = FileEntry::new(display, truncated);
+                            entry.tokens = tokenizer.count(&entry.content);
+                            entry.truncated = true;
+                            entry
+                        }
+                        // Files at a revision are not on disk, so they are kept as read
+                        None if revision.is_some() => {
+                            let mut entry = FileEntry::new(display, text.content);
+                            entry.tokens = tokens;
+                            entry
+                        }
+                        None => {
+                            let mut entry = FileEntry::on_disk(display, path, text.content.len());
+                            entry.tokens = tokens;
+                            entry
+                        }
+                    };
+                    entry.encoding = text.encoding.map(str::to_string);
+                    Ok(Candidate { entry, modified })
+                }
+                // Binary and unreadable files stay in the tree and are listed with the reason
+                Err(reason) => Err(SkippedFile { path: display, reason }),
+            };
+            (path.clone(), stats, scanned)
         })
-        .collect::<Result<Vec<_>>>()?;
+        .collect::<Vec<_>>();
+    let mut candidates = Vec::new();
+    let mut skipped = Vec::new();
+    let mut stats = HashMap::new();
+    for (path, file_stats, file) in scanned {
+        let in_image = in_image(&path);
+        let history = histories.remove(&path);
+        stats.insert(path, file_stats);
+        if !in_image {
+            continue;
+        }
+        match file {
+            Ok(mut candidate) => {
+                candidate.entry.history = history;
+                candidates.push(candidate);
+            }
+            Err(file) => skipped.push(file),
+        }
+    }
+
+    // Collapsing only shortens the tree; every file is still a candidate for the image
+    let (max_depth, collapse_over) = config.tree_limits();
+    let tree = tree_root.build_with(&RenderOptions {
+        annotations,
+        stats,
+        max_depth,
+        collapse_over,
+    });
+
+    // Fit the files into the token budget, if there is one
+    let (processed_files, omitted) = match config.max_tokens() {
+        Some(max_tokens) => {
+            let selection = budget::select(candidates, &tree, max_tokens, tokenizer)?;
+            (selection.files, selection.omitted)
+        }
+        None => (candidates.into_iter().map(|c| c.entry).collect(), Vec::new()),
+    };
 
     // Create repository structure
-    let repo = Repository {
-        tree,
-        files: processed_files,
+    let mut repo = Repository::new(tree, processed_files);
+    repo.omitted = omitted;
+    repo.skipped = skipped;
+    repo.git = match config.revision() {
+        Some(rev) => git::revision_metadata(&target_dir, rev),
+        None => git::metadata(&target_dir),
     };
+    let skipped_summary = summarize_skipped(&repo.skipped);
+    let omitted_count = repo.omitted.len();
+    let omitted_tokens = repo.omitted.iter().map(|f| f.tokens).sum::<usize>();
+    let changed_count = repo.files.len() + omitted_count + repo.skipped.len();
 
-    // Format output
-    let output = format_output(repo, config.format())?;
+    // Split into parts if requested; every part repeats the tree
+    let parts = match config.split_size() {
+        Some(limit) => split::split(repo, limit, config.format(), tokenizer)?,
+        None => vec![repo],
+    };
     
-    // Ensure output directory exists
-    let jockey_dir = root_dir.join(OUTPUT_DIR);
-    fs::create_dir_all(&jockey_dir).await.map_err(|e| {
-        JockeyError::Processing(format!("Failed to create {} directory: {}", OUTPUT_DIR, e))
-    })?;
-
-    // Generate output path with project name and date
     let project_name = root_dir
         .file_name()
         .and_then(|n| n.to_str())
         .unwrap_or("project");
-    let extension = match config.format() {
-        crate::cli::OutputFormat::Md => "md",
-        crate::cli::OutputFormat::Txt => "txt",
-        crate::cli::OutputFormat::Json => "json",
-        crate::cli::OutputFormat::Yaml => "yaml",
-    };
-    
-    // Get unique filename
-    let filename = get_unique_filename(&jockey_dir, project_name, extension).await?;
-    let output_path = jockey_dir.join(&filename);
-    
-    // Write output in a single operation
-    fs::write(&output_path, output).await.map_err(|e| {
-        JockeyError::Processing(format!("Failed to write output file: {}", e))
-    })?;
-
-    // Get and format file size
-    let file_size = fs::metadata(&output_path).await.map_err(|e| {
-        JockeyError::Processing(format!("Failed to get file size: {}", e))
-    })?.len();
-
-    // Get relative path for display
-    let relative_path = output_path.strip_prefix(&root_dir)
-        .unwrap_or(&output_path)
-        .to_string_lossy();
-
-    // Print success message
-    println!("\n{}", "Jockey image created successfully!".green().bold());
-    println!("{} {} ({})", 
-        "Location:".blue(),
-        relative_path.yellow(),
-        format_file_size(file_size).cyan()
-    );
+    write_image(config, &root_dir, project_name, &parts).await?;
+    if let Some(rev) = config.revision() {
+        report(config, format!("{} {} files at {}",
+            "Revision:".blue(),
+            files.len().to_string().yellow(),
+            rev
+        ));
+    }
+    if let Some(changes) = &changes {
+        report(config, format!("{} {} of {} files ({})",
+            "Changed:".blue(),
+            changed_count.to_string().yellow(),
+            files.len(),
+            changes
+        ));
+    }
+    if marked_count > 0 {
+        report(config, format!("{} {} files marked generated, vendored or -diff in .gitattributes (--include-generated adds them)",
+            "Left out:".blue(),
+            marked_count.to_string().yellow()
+        ));
+    }
+    if omitted_count > 0 {
+        report(config, format!("{} {} files ({} tokens) did not fit the token budget",
+            "Omitted:".blue(),
+            omitted_count.to_string().yellow(),
+            format_count(omitted_tokens).yellow()
+        ));
+    }
+    if let Some(summary) = skipped_summary {
+        report(config, format!("{} {}", "Skipped:".blue(), summary));
+    }
     
     Ok(())
 } 
\ No newline at end of file
@@ -0,0 +1,183 @@
+use crate::cli::{Config, PathStyle};
+use crate::error::{Result, JockeyError};
+use crate::git::{self, ChangeSet};
+use crate::output::{FileEntry, Repository, SkippedFile};
+use crate::processor::{report, write_image};
+use crate::sniff;
+use crate::tokens::default_tokenizer;
+use crate::attributes::ATTRIBUTES_FILENAME;
+use crate::tree::{TreeBuilder, TreeOrder, GITIGNORE_FILENAME, IGNORE_FILENAME};
+use similar::TextDiff;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use colored::*;
+
+/// Directories whose files are tests
+const TEST_DIRS: &[&str] = &["tests", "test", "__tests__", "spec", "specs"];
+
+/// Name affixes that mark test files, e.g. `parser_test.go`, `parser.spec.ts`, `test_parser.py`
+const TEST_SUFFIXES: &[&str] = &[".test", ".spec", "_test", "_tests", "_spec", "-test", "-spec", "Test", "Tests", "Spec"];
+const TEST_PREFIXES: &[&str] = &["test_"];
+
+/// Names shared by too many unrelated files to pair sources with tests by
+const GENERIC_NAMES: &[&str] = &["mod", "lib", "main", "index", "__init__", "init", "common", "utils", "helpers", "conftest", "setup"];
+
+/// Builds a review image of the changes since `base`: the diff and new content of every changed
+/// file, followed by related unchanged files such as their tests.
+pub async fn review(config: &Config, base: &str, with_related: bool) -> Result<()> {
+    let root_dir = Config::get_root_dir()?;
+    let toplevel = git::toplevel(&root_dir)?;
+    let merge_base = git::merge_base(&root_dir, base)?;
+    let changed = git::changed_files(&root_dir, &ChangeSet::Since(base.to_string()))?;
+
+    // git reports paths below the canonical work tree; the walked paths are made comparable
+    let canonical_root = std::fs::canonicalize(&root_dir).map_err(|e| {
+        JockeyError::Processing(format!("Failed to resolve '{}': {}", root_dir.display(), e))
+    })?;
+    let canonical = |path: &Path| {
+        path.strip_prefix(&root_dir).map_or_else(|_| path.to_path_buf(), |relative| canonical_root.join(relative))
+    };
+
+    // Ignore rules apply as for `generate`, so changes to excluded files stay out of the image
+    let mut tree_root = TreeBuilder::walk(&root_dir, None, &[], TreeOrder::DirsFirst, false)?;
+    let files = tree_root.files();
+    let changed_files = files
+        .iter()
+        .filter(|path| changed.contains(&canonical(path)))
+        .cloned()
+        .collect::<Vec<_>>();
+    // Deleted files cannot be walked, so their paths are listed together with the ignore and
+    // attribute files on disk that could rule them out
+    let gone = changed
+        .iter()
+        .filter(|path| !path.exists())
+        .filter_map(|path| path.strip_prefix(&canonical_root).ok())
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    let rule_files = gone
+        .iter()
+        .flat_map(|relative| relative.ancestors().skip(1))
+        .flat_map(|dir| [IGNORE_FILENAME, GITIGNORE_FILENAME, ATTRIBUTES_FILENAME].map(|name| dir.join(name)))
+        .filter(|file| canonical_root.join(file).is_file())
+        .collect::<BTreeSet<_>>();
+    let listing = gone.into_iter().chain(rule_files).collect::<Vec<_>>();
+    let read = |relative: &Path| std::fs::read_to_string(canonical_root.join(relative)).ok();
+    let mut deleted = TreeBuilder::from_listing(&canonical_root, &listing, read, None, &[], TreeOrder::DirsFirst, false)?.files();
+    deleted.sort();
+    if changed_files.is_empty() && deleted.is_empty() {
+        return Err(JockeyError::Processing(format!("No changes since '{}' to review", base)));
+    }
+    let related = if with_related {
+        related_files(&root_dir, &changed_files, &files)
+    } else {
+        Vec::new()
+    };
+
+    let tokenizer = default_tokenizer();
+    let diff = |path: &Path, content: &str| -> Result<String> {
+        let display = PathStyle::Relative.display(path, &toplevel);
+        let diff = git::diff_file(&toplevel, &merge_base, &display)?;
+        if !diff.is_empty() {
+            return Ok(diff);
+        }
+        // Untracked files are not in git's diff; they are new in their entirety
+        Ok(TextDiff::from_lines("", content)
+            .unified_diff()
+            .context_radius(3)
+            .header("/dev/null", &format!("b/{}", display))
+            .to_string())
+    };
+
+    let mut changed_entries = Vec::new();
+    let mut related_entries = Vec::new();
+    let mut skipped = Vec::new();
+    for (path, is_related) in changed_files.iter().map(|p| (p, false)).chain(related.iter().map(|p| (p, true))) {
+        let display = PathStyle::Relative.display(path, &root_dir);
+        let text = match sniff::read_text(path) {
+            Ok(text) => text,
+            Err(reason) => {
+                skipped.push(SkippedFile { path: display, reason });
+                continue;
+            }
+        };
+        let mut entry = FileEntry::on_disk(display, path, text.content.len());
+        entry.tokens = tokenizer.count(&text.content);
+        entry.encoding = text.encoding.map(str::to_string);
+        if is_related {
+            entry.related = true;
+            related_entries.push(entry);
+        } else {
+            let diff = diff(&canonical(path), &text.content)?;
+            entry.tokens += tokenizer.count(&diff);
+            entry.diff = Some(diff);
+            changed_entries.push(entry);
+        }
+    }
+    // Deleted files are nothing but their diff; they are not in the tree
+    for path in &deleted {
+        let mut entry = FileEntry::new(PathStyle::Relative.display(path, &canonical_root), String::new());
+        let diff = diff(path, "")?;
+        entry.tokens = tokenizer.count(&diff);
+        entry.diff = Some(diff);
+        entry.deleted = true;
+        changed_entries.push(entry);
+    }
+    // Changes come first, then the context for them
+    let mut entries = changed_entries;
+    entries.extend(related_entries);
+
+    // The tree shows just the files in the image
+    let included = changed_files.iter().chain(&related).collect::<HashSet<_>>();
+    tree_root.retain_files(&|path: &Path| included.contains(&path.to_path_buf()));
+    let mut repo = Repository::new(tree_root.build(), entries);
+    repo.skipped = skipped;
+    repo.git = git::metadata(&root_dir);
+
+    let project_name = root_dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("project");
+    let changed_count = changed_files.len() + deleted.len();
+    let related_count = related.len();
+    write_image(config, &root_dir, &format!("{}_review", project_name), &[repo]).await?;
+    report(config, format!("{} {} files since {} ({} related)",
+        "Changed:".blue(),
+        changed_count.to_string().yellow(),
+        base,
+        related_count.to_string().yellow()
+    ));
+    Ok(())
+}
+
+/// Unchanged files under `root` that belong with the changed ones: the tests of changed sources
+/// and the sources of changed tests, paired by file name.
+pub fn related_files(root: &Path, changed: &[PathBuf], files: &[PathBuf]) -> Vec<PathBuf> {
+    let subject = |path: &Path| subject(path.strip_prefix(root).unwrap_or(path));
+    let subjects = changed.iter().filter_map(|path| subject(path)).collect::<HashSet<_>>();
+    let changed = changed.iter().collect::<HashSet<_>>();
+    files
+        .iter()
+        .filter(|path| !changed.contains(path))
+        .filter(|path| subject(path).is_some_and(|(name, is_test)| subjects.contains(&(name, !is_test))))
+        .cloned()
+        .collect()
+}
+
+/// The name a source file shares with its tests, and whether `path` (relative to the project
+/// root) is a test.
+fn subject(path: &Path) -> Option<(String, bool)> {
+    let stem = path.file_stem()?.to_str()?;
+    let mut is_test = path
+        .parent()
+        .is_some_and(|dir| dir.components().any(|c| TEST_DIRS.contains(&c.as_os_str().to_str().unwrap_or(""))));
+    let mut name = stem;
+    if let Some(stripped) = TEST_SUFFIXES.iter().find_map(|suffix| stem.strip_suffix(suffix)) {
+        name = stripped;
+        is_test = true;
+    } else if let Some(stripped) = TEST_PREFIXES.iter().find_map(|prefix| stem.strip_prefix(prefix)) {
+        name = stripped;
+        is_test = true;
+    }
+    let name = name.to_lowercase();
+    (!name.is_empty() && !GENERIC_NAMES.contains(&name.as_str())).then_some((name, is_test))
+}
@@ -0,0 +1,89 @@
+use crate::encoding;
+use crate::output::SkipReason;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Files larger than this are never read
+pub const MAX_FILE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// How much of the start of a file is inspected when deciding whether it is binary
+const SNIFF_LEN: usize = 8192;
+
+/// Share of control characters above which a file is treated as binary
+const MAX_CONTROL_RATIO: f64 = 0.1;
+
+// Signatures of common binary formats that may not contain a NUL byte near the start
+const MAGIC_NUMBERS: &[&[u8]] = &[
+    b"\x89PNG", b"\xFF\xD8\xFF", b"GIF87a", b"GIF89a", b"%PDF-", b"PK\x03\x04", b"\x1F\x8B",
+    b"\x7FELF", b"\xCA\xFE\xBA\xBE", b"\xCF\xFA\xED\xFE", b"\xCE\xFA\xED\xFE",
+    b"7z\xBC\xAF\x27\x1C", b"Rar!\x1A\x07", b"\xFD7zXZ\0", b"SQLite format 3\0", b"OggS",
+    b"RIFF", b"ID3", b"wOFF", b"wOF2",
+];
+
+/// Contents of a text file, decoded to UTF-8
+#[derive(Debug, PartialEq)]
+pub struct Text {
+    pub content: String,
+    /// Name of the original encoding, when the file was not UTF-8
+    pub encoding: Option<&'static str>,
+}
+
+/// Reads a file as text, decoding UTF-16 and legacy encodings, or says why its contents have to
+/// be left out of the image.
+pub fn read_text(path: &Path) -> Result<Text, SkipReason> {
+    let metadata = std::fs::metadata(path).map_err(|e| io_reason(e.kind()))?;
+    if metadata.len() > MAX_FILE_SIZE {
+        return Err(SkipReason::TooLarge);
+    }
+    let bytes = std::fs::read(path).map_err(|e| io_reason(e.kind()))?;
+    decode(bytes)
+}
+
+/// Decodes the contents of a file that has already been read, e.g. from git's object database.
+pub fn decode(bytes: Vec<u8>) -> Result<Text, SkipReason> {
+    // UTF-16 is full of NUL bytes, so it has to be recognized before sniffing
+    if let Some(utf16) = encoding::utf16_bom(&bytes) {
+        let content = encoding::decode_as(&bytes, utf16).ok_or(SkipReason::NonUtf8)?;
+        return Ok(Text { content, encoding: Some(utf16.name()) });
+    }
+    if is_binary(&bytes) {
+        return Err(SkipReason::Binary);
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(content) => Ok(Text { content, encoding: None }),
+        Err(error) => {
+            let bytes = error.into_bytes();
+            let legacy = encoding::guess_legacy(&bytes);
+            let content = encoding::decode_as(&bytes, legacy).ok_or(SkipReason::NonUtf8)?;
+            Ok(Text { content, encoding: Some(legacy.name()) })
+        }
+    }
+}
+
+/// Sniffs the start of a file: known magic numbers, NUL bytes, or too many control characters.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    if MAGIC_NUMBERS.iter().any(|magic| bytes.starts_with(magic)) {
+        return true;
+    }
+    let sample = &bytes[..bytes.len().min(SNIFF_LEN)];
+    if sample.is_empty() {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+    // Tabs, line breaks, form feeds and ANSI escapes all show up in ordinary text
+    let control = sample
+        .iter()
+        .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0C | 0x1B)) || b == 0x7F)
+        .count();
+    control as f64 / sample.len() as f64 > MAX_CONTROL_RATIO
+}
+
+fn io_reason(kind: ErrorKind) -> SkipReason {
+    match kind {
+        ErrorKind::PermissionDenied => SkipReason::PermissionDenied,
+        _ => SkipReason::Unreadable,
+    }
+}
@@ -0,0 +1,250 @@
+use crate::cli::OutputFormat;
+use crate::error::Result;
+use crate::output::{format_output, FileChunk, FileEntry, FileSource, ImagePart, OmittedFile, Repository, SkippedFile};
+use crate::tokens::Tokenizer;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Conservative characters-per-token ratio used when a single line has to be cut
+const CHARS_PER_TOKEN: usize = 3;
+
+/// Maximum size of one image part, in bytes or in tokens
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitSize {
+    Bytes(usize),
+    Tokens(usize),
+}
+
+impl FromStr for SplitSize {
+    type Err = String;
+
+    /// Parses sizes such as `500kb`, `2mb`, `80000` (bytes), `50000tokens` or `100kt`.
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let normalized = value.trim().to_lowercase().replace([' ', '_', ','], "");
+        let split_at = normalized
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(normalized.len());
+        let (number, unit) = normalized.split_at(split_at);
+        let number = number
+            .parse::<f64>()
+            .map_err(|_| format!("invalid size '{}'", value))?;
+
+        let (multiplier, tokens) = match unit {
+            "" | "b" | "bytes" => (1.0, false),
+            "k" | "kb" => (1024.0, false),
+            "m" | "mb" => (1024.0 * 1024.0, false),
+            "t" | "tokens" => (1.0, true),
+            "kt" | "ktokens" => (1000.0, true),
+            "mt" | "mtokens" => (1_000_000.0, true),
+            _ => return Err(format!("unknown size unit '{}' (use b, kb, mb, tokens, kt or mt)", unit)),
+        };
+        let amount = (number * multiplier) as usize;
+        if amount == 0 {
+            return Err("size must be greater than zero".to_string());
+        }
+
+        Ok(if tokens { SplitSize::Tokens(amount) } else { SplitSize::Bytes(amount) })
+    }
+}
+
+/// Measures images as they are rendered in the output format, so that the parts stay within
+/// the limit however the format frames and escapes their contents.
+struct Measure<'a> {
+    limit: SplitSize,
+    format: OutputFormat,
+    tokenizer: &'a dyn Tokenizer,
+    /// Cost of an image without a tree or files, which the costs of files are measured against
+    empty: usize,
+}
+
+impl<'a> Measure<'a> {
+    fn new(limit: SplitSize, format: OutputFormat, tokenizer: &'a dyn Tokenizer) -> Result<Self> {
+        let mut measure = Self { limit, format, tokenizer, empty: 0 };
+        measure.empty = measure.image(Repository::new("", Vec::new()))?;
+        Ok(measure)
+    }
+
+    fn text(&self, text: &str) -> usize {
+        match self.limit {
+            SplitSize::Bytes(_) => text.len(),
+            SplitSize::Tokens(_) => self.tokenizer.count(text),
+        }
+    }
+
+    fn image(&self, repo: Repository) -> Result<usize> {
+        Ok(self.text(&format_output(repo, self.format.clone())?))
+    }
+
+    /// What `entry` adds to an image: its contents together with its heading, notes and framing.
+    fn file(&self, entry: &FileEntry) -> Result<usize> {
+        let entry = entry.load()?.into_owned();
+        Ok(self.image(Repository::new("", vec![entry]))?.saturating_sub(self.empty))
+    }
+
+    /// What the omitted and skipped lists add to the last part.
+    fn lists(&self, omitted: &[OmittedFile], skipped: &[SkippedFile]) -> Result<usize> {
+        if omitted.is_empty() && skipped.is_empty() {
+            return Ok(0);
+        }
+        let mut repo = Repository::new("", Vec::new());
+        repo.omitted = omitted.to_vec();
+        repo.skipped = skipped.to_vec();
+        Ok(self.image(repo)?.saturating_sub(self.empty))
+    }
+
+    /// Room left for files once the header, the tree and the footer are in place. Very large
+    /// trees still leave a quarter of every part for files.
+    fn capacity(&self, repo: &Repository) -> Result<usize> {
+        let limit = match self.limit {
+            SplitSize::Bytes(limit) | SplitSize::Tokens(limit) => limit,
+        };
+        // The header is measured with the widest token count and part number any part can show
+        let mut header = Repository::new(repo.tree.clone(), Vec::new());
+        header.tokens = repo.tokens;
+        header.git = repo.git.clone();
+        header.part = Some(ImagePart { index: usize::MAX, count: usize::MAX });
+        Ok(limit.saturating_sub(self.image(header)?).max(limit / 4).max(1))
+    }
+}
+
+/// Splits an image into parts that each fit `limit` when rendered in `format`, repeating the
+/// tree in every part. Files are only cut into chunks when a single file is larger than a part.
+/// The omitted and skipped lists are not cut, so only they can make the last part too large.
+/// Returns the image unchanged when it already fits.
+pub fn split(repo: Repository, limit: SplitSize, format: OutputFormat, tokenizer: &dyn Tokenizer) -> Result<Vec<Repository>> {
+    let measure = Measure::new(limit, format, tokenizer)?;
+    let capacity = measure.capacity(&repo)?;
+
+    let mut parts: Vec<Vec<FileEntry>> = vec![Vec::new()];
+    let mut used = 0;
+    for file in repo.files {
+        let cost = measure.file(&file)?;
+        let pieces = if cost > capacity {
+            chunk_file(file, capacity, &measure)?
+        } else {
+            vec![(file, cost)]
+        };
+        for (piece, cost) in pieces {
+            if used + cost > capacity && !parts.last().is_some_and(|p| p.is_empty()) {
+                parts.push(Vec::new());
+                used = 0;
+            }
+            used += cost;
+            parts.last_mut().expect("at least one part").push(piece);
+        }
+    }
+
+    // The omitted and skipped lists close the last part, in a part of their own if need be
+    let lists = measure.lists(&repo.omitted, &repo.skipped)?;
+    if used + lists > capacity && !parts.last().is_some_and(|p| p.is_empty()) {
+        parts.push(Vec::new());
+    }
+
+    let count = parts.len();
+    let mut images = parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, files)| {
+            let mut image = Repository::new(repo.tree.clone(), files);
+            image.git = repo.git.clone();
+            if count > 1 {
+                image.part = Some(ImagePart { index: index + 1, count });
+            }
+            image
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(last) = images.last_mut() {
+        last.omitted = repo.omitted;
+        last.skipped = repo.skipped;
+    }
+    Ok(images)
+}
+
+/// Cuts a file into line-aligned chunks that each fit `capacity`, cutting inside a line only
+/// when that line alone is too large, and returns them with their measured cost. Pieces of a
+/// file that is still on disk stay on disk as byte ranges of it.
+fn chunk_file(file: FileEntry, capacity: usize, measure: &Measure) -> Result<Vec<(FileEntry, usize)>> {
+    let loaded = file.load()?;
+    let content = loaded.content.as_str();
+
+    // Pieces keep everything known about the file, such as its encoding
+    let template = FileEntry { content: String::new(), source: None, ..file.clone() };
+    let overhead = measure.file(&FileEntry { chunk: Some(FileChunk { index: usize::MAX, count: usize::MAX }), ..template.clone() })?;
+    let mut room = capacity.saturating_sub(overhead).max(1);
+    loop {
+        let ranges = chunk_ranges(content, room, measure);
+        let count = ranges.len();
+        let mut pieces = Vec::with_capacity(count);
+        let mut largest = 0;
+        for (index, range) in ranges.into_iter().enumerate() {
+            let mut entry = template.clone();
+            entry.tokens = measure.tokenizer.count(&content[range.clone()]);
+            entry.chunk = Some(FileChunk { index: index + 1, count });
+            // Pieces are measured with their contents in memory rather than re-read from disk
+            let cost = measure.file(&FileEntry { content: content[range.clone()].to_string(), ..entry.clone() })?;
+            largest = largest.max(cost);
+            match &file.source {
+                Some(source) => entry.source = Some(FileSource { range: Some(range), ..source.clone() }),
+                None => entry.content = content[range].to_string(),
+            }
+            pieces.push((entry, cost));
+        }
+        // Escaping and indentation can make the rendered contents larger than the raw ones
+        if largest <= capacity || room == 1 {
+            return Ok(pieces);
+        }
+        room = (room * capacity / largest).min(room - 1).max(1);
+    }
+}
+
+/// Byte ranges of line-aligned pieces of `content` that each measure at most `room`.
+fn chunk_ranges(content: &str, room: usize, measure: &Measure) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    let mut current_size = 0;
+    for line in content.split_inclusive('\n') {
+        for segment in cut_line(line, room, measure) {
+            let size = measure.text(segment);
+            if end > start && current_size + size > room {
+                ranges.push(start..end);
+                start = end;
+                current_size = 0;
+            }
+            end += segment.len();
+            current_size += size;
+        }
+    }
+    if end > start || ranges.is_empty() {
+        ranges.push(start..end);
+    }
+    ranges
+}
+
+fn cut_line<'a>(line: &'a str, room: usize, measure: &Measure) -> Vec<&'a str> {
+    if measure.text(line) <= room {
+        return vec![line];
+    }
+    let max_bytes = match measure.limit {
+        SplitSize::Bytes(_) => room,
+        SplitSize::Tokens(_) => room * CHARS_PER_TOKEN,
+    }
+    .max(4);
+
+    let mut segments = Vec::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        let mut end = max_bytes.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (segment, remainder) = rest.split_at(end);
+        segments.push(segment);
+        rest = remainder;
+    }
+    segments
+}
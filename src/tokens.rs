@@ -0,0 +1,71 @@
+use std::sync::OnceLock;
+use tiktoken_rs::CoreBPE;
+
+/// Counts tokens the way a particular model family's tokenizer would.
+pub trait Tokenizer: Send + Sync {
+    /// Short name shown next to counts, e.g. `cl100k`
+    fn name(&self) -> &'static str;
+
+    fn count(&self, text: &str) -> usize;
+}
+
+/// The cl100k_base byte-pair encoding, with its vocabulary embedded in the binary so counting
+/// works offline.
+pub struct Cl100k {
+    bpe: CoreBPE,
+}
+
+impl Cl100k {
+    pub fn new() -> Self {
+        Self {
+            bpe: tiktoken_rs::cl100k_base().expect("embedded cl100k vocabulary is valid"),
+        }
+    }
+}
+
+impl Default for Cl100k {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tokenizer for Cl100k {
+    fn name(&self) -> &'static str {
+        "cl100k"
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+}
+
+/// Returns the shared tokenizer used for image statistics. Loading the vocabulary is not free,
+/// so it happens once per process.
+pub fn default_tokenizer() -> &'static dyn Tokenizer {
+    static TOKENIZER: OnceLock<Cl100k> = OnceLock::new();
+    TOKENIZER.get_or_init(Cl100k::new)
+}
+
+/// Formats a count with thousands separators, e.g. `12,345`.
+pub fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(c);
+    }
+    formatted
+}
+
+/// Formats a count compactly for tight spaces such as tree annotations: `950`, `3.2k`, `1.4M`.
+pub fn format_compact(count: usize) -> String {
+    if count < 1000 {
+        count.to_string()
+    } else if count < 999_950 {
+        format!("{:.1}k", count as f64 / 1000.0)
+    } else {
+        format!("{:.1}M", count as f64 / 1_000_000.0)
+    }
+}
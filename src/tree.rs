@@ -1,6 +1,14 @@
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use crate::attributes::{Attributes, Marker, ATTRIBUTES_FILENAME};
 use crate::error::{Result, JockeyError};
+use crate::git;
+use crate::format::format_file_size;
+use crate::tokens::{format_compact, format_count};
+use clap::ValueEnum;
+use glob::{MatchOptions, Pattern};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
 use ignore::WalkBuilder;
 
 const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
@@ -27,11 +35,153 @@ const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
     ".env", ".env.*", "*.pem", "*.key",
 ];
 
+/// Per-directory ignore files in gitignore syntax, for files that are tracked in git but should
+/// stay out of images
+pub const IGNORE_FILENAME: &str = ".jockeyignore";
+
+/// Git's own ignore files, which listed trees read like `.jockeyignore`
+pub const GITIGNORE_FILENAME: &str = ".gitignore";
+
+/// Allow-list of glob patterns. Patterns without a `/` match a file or directory name at any
+/// depth; other patterns match the path relative to the root. A file is included when the
+/// pattern matches it or one of its parent directories, so `--include src` keeps all of `src/`.
+struct IncludeSet(Vec<(Pattern, bool)>);
+
+impl IncludeSet {
+    fn new(patterns: &[String]) -> Result<Self> {
+        patterns
+            .iter()
+            .map(|pattern| {
+                let trimmed = pattern.trim().trim_start_matches("./").trim_end_matches('/');
+                let anchored = trimmed.contains('/');
+                Pattern::new(trimmed).map(|glob| (glob, anchored)).map_err(|e| {
+                    JockeyError::Config(format!("Invalid include pattern '{}': {}", pattern, e))
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(IncludeSet)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn matches(&self, relative: &Path) -> bool {
+        if self.0.is_empty() {
+            return true;
+        }
+        let options = MatchOptions { require_literal_separator: true, ..MatchOptions::new() };
+        let components = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+        (1..=components.len()).any(|end| {
+            let prefix = components[..end].join("/");
+            self.0.iter().any(|(pattern, anchored)| {
+                if *anchored {
+                    pattern.matches_with(&prefix, options)
+                } else {
+                    pattern.matches_with(&components[end - 1], options)
+                }
+            })
+        })
+    }
+}
+
+/// Default and user-specified exclude patterns, in gitignore syntax relative to `root`
+fn exclude_overrides(root: &Path, exclude_patterns: Option<&String>) -> Result<Override> {
+    let mut overrides = OverrideBuilder::new(root);
+    let user_patterns = exclude_patterns
+        .map(|patterns| patterns.split(',').map(str::trim).filter(|p| !p.is_empty()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    for pattern in DEFAULT_IGNORE_PATTERNS.iter().copied().chain(user_patterns) {
+        overrides.add(&format!("!{}", pattern)).map_err(|e| {
+            JockeyError::Config(format!("Invalid exclude pattern '{}': {}", pattern, e))
+        })?;
+    }
+    overrides.build().map_err(|e| {
+        JockeyError::Config(format!("Invalid exclude patterns: {}", e))
+    })
+}
+
+/// Order of siblings in the rendered tree
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum TreeOrder {
+    /// Directories before files, each group sorted by name
+    #[default]
+    DirsFirst,
+    /// Directories and files mixed, sorted by name
+    Alphabetical,
+}
+
+/// A per-file value that can be shown next to tree nodes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TreeAnnotation {
+    Size,
+    Lines,
+    Tokens,
+}
+
+/// Measurements of one file, used to annotate the tree
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FileStats {
+    pub size: u64,
+    /// Line and token counts are only known for text files
+    pub lines: Option<usize>,
+    pub tokens: Option<usize>,
+}
+
+/// Options for rendering a tree
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    /// Values shown next to each node; directories show totals over every file below them
+    pub annotations: Vec<TreeAnnotation>,
+    pub stats: HashMap<PathBuf, FileStats>,
+    /// Deepest level rendered; directories at this level are collapsed. The root is level 0.
+    pub max_depth: Option<usize>,
+    /// Directories with more entries than this are collapsed
+    pub collapse_over: Option<usize>,
+}
+
+/// Totals over the files below a node
+#[derive(Clone, Copy, Default)]
+struct Totals {
+    files: usize,
+    size: u64,
+    lines: usize,
+    tokens: usize,
+}
+
+/// What a node's label needs to know about the files below it, rolled up once per render
+#[derive(Default)]
+struct Summary {
+    totals: Totals,
+    /// Markers of the files below
+    markers: BTreeSet<Marker>,
+    /// Whether every file below is marked
+    all_marked: bool,
+}
+
+impl Summary {
+    /// Markers shared by every file below, e.g. `linguist-vendored` for a file or a directory
+    /// of vendored files. `None` if any file is unmarked or there are no files.
+    fn markers(&self) -> Option<String> {
+        if !self.all_marked || self.markers.is_empty() {
+            return None;
+        }
+        Some(self.markers.iter().map(|marker| marker.to_string()).collect::<Vec<_>>().join(", "))
+    }
+}
+
 #[derive(Clone)]
 pub struct TreeBuilder {
     name: String,
-    prefix: String,
-    is_last: bool,
+    path: PathBuf,
+    is_dir: bool,
+    is_file: bool,
+    /// Set on files that `.gitattributes` marks as generated, vendored or `-diff`; they stay
+    /// in the tree but are left out of the image
+    marker: Option<Marker>,
     children: Vec<TreeBuilder>,
 }
 
@@ -43,96 +193,365 @@ impl TreeBuilder {
                 .unwrap_or_default()
                 .to_string_lossy()
                 .into_owned(),
-            prefix: String::new(),
-            is_last: false,
+            path: path.as_ref().to_path_buf(),
+            is_dir: true,
+            is_file: false,
+            marker: None,
             children: Vec::new(),
         }
     }
 
-    pub fn process_directory(root: impl AsRef<Path>, exclude_patterns: Option<&String>) -> Result<(String, Vec<PathBuf>)> {
+    /// Walks `root` and returns the rendered tree together with the files to include, listed in
+    /// the same order as they appear in the tree.
+    pub fn process_directory(root: impl AsRef<Path>, exclude_patterns: Option<&String>, include_patterns: &[String], order: TreeOrder, include_marked: bool) -> Result<(String, Vec<PathBuf>)> {
+        let tree = Self::walk(root, exclude_patterns, include_patterns, order, include_marked)?;
+        Ok((tree.build(), tree.files()))
+    }
+
+    /// Walks `root` into a sorted tree, ready to be rendered once the files have been measured.
+    /// When include patterns are given, only files matching one of them are kept. Unless
+    /// `include_marked` is set, files marked `linguist-generated`, `linguist-vendored` or `-diff`
+    /// in `.gitattributes` are shown in the tree but not returned by `files`.
+    pub fn walk(root: impl AsRef<Path>, exclude_patterns: Option<&String>, include_patterns: &[String], order: TreeOrder, include_marked: bool) -> Result<Self> {
         let root = root.as_ref();
-        let mut files = Vec::new();
-        let mut nodes = HashMap::new();
+        let includes = IncludeSet::new(include_patterns)?;
+        let mut attributes = Attributes::default();
+        if !include_marked {
+            // Rules from above the root reach into it too
+            for dir in git::ancestors_in_work_tree(root) {
+                attributes.load_dir(&dir)?;
+            }
+            attributes.load_dir(root)?;
+        }
         
         // Build walker with ignore patterns
         let mut walker = WalkBuilder::new(root);
         walker.hidden(true); // Skip hidden files by default
+        walker.add_custom_ignore_filename(IGNORE_FILENAME);
         
-        // Add default ignore patterns
-        for pattern in DEFAULT_IGNORE_PATTERNS {
-            walker.add_ignore(pattern);
-        }
+        walker.overrides(exclude_overrides(root, exclude_patterns)?);
         
-        // Add user-specified patterns
-        if let Some(patterns) = exclude_patterns {
-            for pattern in patterns.split(',') {
-                walker.add_ignore(pattern.trim());
-            }
-        }
-        
-        // First pass: collect all paths and create nodes
+        // Attach every entry under its parent by path components, so the result does not depend
+        // on the order the walker yields entries in
+        let mut root_node = TreeBuilder::new(root);
         for entry in walker.build() {
             let entry = entry.map_err(|e| {
                 JockeyError::Processing(format!("Failed to read directory entry: {}", e))
             })?;
             
-            let path = entry.path().to_path_buf();
-            if entry.file_type().map_or(false, |ft| ft.is_file()) {
-                files.push(path.clone());
+            let relative = match entry.path().strip_prefix(root) {
+                Ok(relative) if !relative.as_os_str().is_empty() => relative,
+                _ => continue,
+            };
+            let mut node = TreeBuilder::new(entry.path());
+            node.is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+            node.is_file = entry.file_type().is_some_and(|ft| ft.is_file());
+            if !node.is_dir && !includes.matches(relative) {
+                continue;
+            }
+            // Directories are yielded before their contents, so their attributes are in place
+            if node.is_dir && !include_marked {
+                attributes.load_dir(entry.path())?;
             }
-            nodes.insert(path, TreeBuilder::new(entry.path()));
+            if node.is_file {
+                node.marker = attributes.marker(entry.path());
+            }
+            root_node.insert(relative, node);
         }
-        
-        // Second pass: build tree structure
-        let mut root_node = TreeBuilder::new(root);
-        let to_process = nodes.keys().cloned().collect::<Vec<_>>();
-        
-        for path in to_process {
-            if let Some(parent) = path.parent() {
-                if let Some(node) = nodes.remove(&path) {
-                    if parent == root {
-                        root_node.add_child(node);
-                    } else {
-                        let parent_path = parent.to_path_buf();
-                        if let Some(parent_node) = nodes.get_mut(&parent_path) {
-                            parent_node.add_child(node);
-                        }
-                    }
+        if !includes.is_empty() {
+            // Directories left without a matching file are noise in an allow-listed tree
+            root_node.prune_empty_dirs();
+        }
+        root_node.sort(order);
+        Ok(root_node)
+    }
+
+    /// Builds the tree of files that are listed rather than walked, such as the files of a git
+    /// revision. `paths` are relative to `root`, and `read` returns the contents of a listed
+    /// `.jockeyignore`, `.gitignore` or `.gitattributes`, or of one above `root` such as
+    /// `../.gitignore`. The same rules apply as in `walk`: hidden files, exclude patterns and
+    /// ignore files leave files out, and include patterns keep them.
+    pub fn from_listing(root: impl AsRef<Path>, paths: &[PathBuf], read: impl Fn(&Path) -> Option<String>, exclude_patterns: Option<&String>, include_patterns: &[String], order: TreeOrder, include_marked: bool) -> Result<Self> {
+        let root = root.as_ref();
+        let includes = IncludeSet::new(include_patterns)?;
+        let overrides = exclude_overrides(root, exclude_patterns)?;
+
+        // Ignore and attribute files are taken from the listing, not from the working directory,
+        // together with those above the root up to the top of the work tree (`../.gitignore`)
+        let ignore_matcher = |dir: &Path, relative: &Path| -> Result<Option<Gitignore>> {
+            let Some(contents) = read(relative) else { return Ok(None) };
+            let mut builder = GitignoreBuilder::new(dir);
+            for line in contents.lines() {
+                builder.add_line(Some(root.join(relative)), line).map_err(|e| {
+                    JockeyError::Config(format!("Invalid pattern in '{}': {}", relative.display(), e))
+                })?;
+            }
+            builder.build().map(Some).map_err(|e| {
+                JockeyError::Config(format!("Invalid patterns in '{}': {}", relative.display(), e))
+            })
+        };
+        let mut ignores = HashMap::new();
+        let mut gitignores = HashMap::new();
+        let mut attributes = Attributes::default();
+        for (up, dir) in git::ancestors_in_work_tree(root).into_iter().enumerate() {
+            let relative = std::iter::repeat_n(Path::new(".."), up + 1).collect::<PathBuf>();
+            if let Some(matcher) = ignore_matcher(&dir, &relative.join(GITIGNORE_FILENAME))? {
+                gitignores.insert(dir.clone(), matcher);
+            }
+            if !include_marked {
+                if let Some(contents) = read(&relative.join(ATTRIBUTES_FILENAME)) {
+                    attributes.load(&dir, &contents)?;
                 }
             }
         }
+        for relative in paths {
+            let (Some(name), Some(parent)) = (relative.file_name(), relative.parent()) else { continue };
+            let dir = root.join(parent);
+            if name == IGNORE_FILENAME || name == GITIGNORE_FILENAME {
+                if let Some(matcher) = ignore_matcher(&dir, relative)? {
+                    let map = if name == IGNORE_FILENAME { &mut ignores } else { &mut gitignores };
+                    map.insert(dir, matcher);
+                }
+            } else if name == ATTRIBUTES_FILENAME && !include_marked {
+                if let Some(contents) = read(relative) {
+                    attributes.load(&dir, &contents)?;
+                }
+            }
+        }
+
+        let mut root_node = TreeBuilder::new(root);
+        for relative in paths {
+            let path = root.join(relative);
+            let hidden = relative.components().any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+            // A pattern that matches a directory leaves out everything below it
+            let excluded = overrides.matched(&path, false).is_ignore()
+                || relative
+                    .ancestors()
+                    .skip(1)
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .any(|dir| overrides.matched(root.join(dir), true).is_ignore());
+            // The ignore file closest to the file decides, and `.jockeyignore` before `.gitignore`
+            // in the same directory, as when walking
+            let ignored = path
+                .ancestors()
+                .skip(1)
+                .flat_map(|dir| [ignores.get(dir), gitignores.get(dir)].into_iter().flatten())
+                .map(|matcher| matcher.matched_path_or_any_parents(&path, false))
+                .find(|matched| !matched.is_none())
+                .is_some_and(|matched| matched.is_ignore());
+            if hidden || excluded || ignored || !includes.matches(relative) {
+                continue;
+            }
+            let mut node = TreeBuilder::new(&path);
+            node.is_dir = false;
+            node.is_file = true;
+            node.marker = attributes.marker(&path);
+            root_node.insert(relative, node);
+        }
+        root_node.sort(order);
+        Ok(root_node)
+    }
+
+    /// Files in the tree, in the order they are rendered, without those marked in `.gitattributes`
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        self.collect_files(&mut files);
+        files
+    }
 
-        Ok((root_node.build(), files))
+    /// Number of files marked in `.gitattributes`, which `files` leaves out
+    pub fn marked_count(&self) -> usize {
+        self.children
+            .iter()
+            .map(|child| usize::from(child.marker.is_some()) + child.marked_count())
+            .sum()
     }
 
     pub fn add_child(&mut self, child: Self) {
         self.children.push(child);
     }
 
-    pub fn build(&self) -> String {
-        let mut result = String::with_capacity(4096); // Pre-allocate buffer
-        self.build_internal(&mut result, "");
-        result
+    /// Places `node` at `relative` below this node, creating any missing directories on the way.
+    fn insert(&mut self, relative: &Path, node: Self) {
+        let mut components = relative.components().collect::<Vec<_>>();
+        components.pop();
+
+        let mut parent = self;
+        for component in components {
+            let name = component.as_os_str().to_string_lossy();
+            // Siblings arrive together, so the parent is almost always the last child
+            let index = match parent.children.iter().rposition(|c| c.is_dir && c.name == name) {
+                Some(index) => index,
+                None => {
+                    parent.add_child(TreeBuilder::new(parent.path.join(component)));
+                    parent.children.len() - 1
+                }
+            };
+            parent = &mut parent.children[index];
+        }
+
+        // The walker may list a directory after one of its descendants created it
+        if !parent.children.iter().any(|c| c.is_dir && node.is_dir && c.name == node.name) {
+            parent.add_child(node);
+        }
+    }
+
+    /// Drops the files `keep` rejects, along with the directories left without files.
+    pub fn retain_files(&mut self, keep: &impl Fn(&Path) -> bool) {
+        self.children.retain_mut(|child| {
+            if child.is_dir {
+                child.retain_files(keep);
+                !child.children.is_empty()
+            } else {
+                keep(&child.path)
+            }
+        });
+    }
+
+    /// Removes directories that contain no files, however deeply. Returns whether anything is left.
+    fn prune_empty_dirs(&mut self) -> bool {
+        self.children.retain_mut(|child| !child.is_dir || child.prune_empty_dirs());
+        !self.children.is_empty()
     }
 
-    fn build_internal(&self, result: &mut String, prefix: &str) {
-        result.push_str(prefix);
-        if !self.prefix.is_empty() {
-            result.push_str(&self.prefix);
+    /// Sorts the children of every node.
+    pub fn sort(&mut self, order: TreeOrder) {
+        self.children.sort_by(|a, b| {
+            let group = match order {
+                TreeOrder::DirsFirst => b.is_dir.cmp(&a.is_dir),
+                TreeOrder::Alphabetical => std::cmp::Ordering::Equal,
+            };
+            group
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        for child in &mut self.children {
+            child.sort(order);
         }
-        result.push_str(&self.name);
+    }
+
+    fn collect_files(&self, files: &mut Vec<PathBuf>) {
+        for child in &self.children {
+            if child.is_file && child.marker.is_none() {
+                files.push(child.path.clone());
+            }
+            child.collect_files(files);
+        }
+    }
+
+    pub fn build(&self) -> String {
+        self.build_with(&RenderOptions::default())
+    }
+
+    pub fn build_with(&self, options: &RenderOptions) -> String {
+        let mut result = String::with_capacity(4096); // Pre-allocate buffer
+        let mut summaries = HashMap::new();
+        self.summarize(&options.stats, &mut summaries);
+        let collapsed = self.is_collapsed(0, options, &summaries);
+        result.push_str(&self.label(options, collapsed, &summaries));
         result.push('\n');
+        if !collapsed {
+            self.build_internal(&mut result, "", 1, options, &summaries);
+        }
+        result
+    }
 
+    fn build_internal(&self, result: &mut String, prefix: &str, depth: usize, options: &RenderOptions, summaries: &HashMap<&Path, Summary>) {
         let len = self.children.len();
         for (i, child) in self.children.iter().enumerate() {
             let is_last = i == len - 1;
             let child_prefix = if is_last { "└── " } else { "├── " };
             let child_continue = if is_last { "    " } else { "│   " };
+            let collapsed = child.is_collapsed(depth, options, summaries);
             
-            let mut child = child.clone();
-            child.prefix = child_prefix.to_string();
-            child.is_last = is_last;
-            child.build_internal(result, &format!("{}{}", prefix, child_continue));
+            result.push_str(prefix);
+            result.push_str(child_prefix);
+            result.push_str(&child.label(options, collapsed, summaries));
+            result.push('\n');
+            if !collapsed {
+                child.build_internal(result, &format!("{}{}", prefix, child_continue), depth + 1, options, summaries);
+            }
         }
     }
-} 
\ No newline at end of file
+
+    /// Whether this node's children are hidden, because it sits at the depth limit, is too wide
+    /// or holds nothing but files marked in `.gitattributes`.
+    fn is_collapsed(&self, depth: usize, options: &RenderOptions, summaries: &HashMap<&Path, Summary>) -> bool {
+        !self.children.is_empty()
+            && (options.max_depth.is_some_and(|max| depth >= max)
+                || options.collapse_over.is_some_and(|max| self.children.len() > max)
+                || summaries[self.path.as_path()].markers().is_some())
+    }
+
+    /// Name of the node, followed by the requested annotations, e.g. `src/ (14 files, 3.2k tokens)`.
+    /// Collapsed directories always say how many files they hide.
+    fn label(&self, options: &RenderOptions, collapsed: bool, summaries: &HashMap<&Path, Summary>) -> String {
+        let summary = &summaries[self.path.as_path()];
+        let markers = summary.markers();
+        if options.annotations.is_empty() && !collapsed && markers.is_none() {
+            return self.name.clone();
+        }
+
+        let totals = summary.totals;
+        let mut values = Vec::new();
+        if self.is_dir {
+            let noun = if totals.files == 1 { "file" } else { "files" };
+            let state = if collapsed && markers.is_none() { " collapsed" } else { "" };
+            values.push(format!("{} {}{}", format_count(totals.files), noun, state));
+        }
+        let stats = options.stats.get(&self.path);
+        // Marked files are never read, so there is nothing to annotate them with
+        let annotations = if markers.is_some() { &[][..] } else { &options.annotations[..] };
+        for annotation in annotations {
+            match annotation {
+                TreeAnnotation::Size => values.push(format_file_size(totals.size)),
+                // Binary files have no line or token counts of their own
+                TreeAnnotation::Lines if self.is_dir || stats.is_some_and(|s| s.lines.is_some()) => {
+                    let noun = if totals.lines == 1 { "line" } else { "lines" };
+                    values.push(format!("{} {}", format_compact(totals.lines), noun));
+                }
+                TreeAnnotation::Tokens if self.is_dir || stats.is_some_and(|s| s.tokens.is_some()) => {
+                    let noun = if totals.tokens == 1 { "token" } else { "tokens" };
+                    values.push(format!("{} {}", format_compact(totals.tokens), noun));
+                }
+                _ => {}
+            }
+        }
+
+        values.extend(markers);
+        let name = if self.is_dir { format!("{}/", self.name) } else { self.name.clone() };
+        if values.is_empty() {
+            name
+        } else {
+            format!("{} ({})", name, values.join(", "))
+        }
+    }
+
+    /// Rolls up the totals and markers of every node, bottom-up, so each node is visited once.
+    fn summarize<'a>(&'a self, stats: &HashMap<PathBuf, FileStats>, summaries: &mut HashMap<&'a Path, Summary>) {
+        let mut summary = Summary { all_marked: true, ..Summary::default() };
+        if let Some(file) = stats.get(&self.path) {
+            summary.totals.files = 1;
+            summary.totals.size = file.size;
+            summary.totals.lines = file.lines.unwrap_or(0);
+            summary.totals.tokens = file.tokens.unwrap_or(0);
+        } else if self.is_file {
+            summary.totals.files = 1;
+        }
+        if self.is_file {
+            summary.all_marked = self.marker.is_some();
+            summary.markers.extend(self.marker);
+        }
+        for child in &self.children {
+            child.summarize(stats, summaries);
+            let child = &summaries[child.path.as_path()];
+            summary.totals.files += child.totals.files;
+            summary.totals.size += child.totals.size;
+            summary.totals.lines += child.totals.lines;
+            summary.totals.tokens += child.totals.tokens;
+            summary.all_marked &= child.all_marked;
+            summary.markers.extend(child.markers.iter().copied());
+        }
+        summaries.insert(&self.path, summary);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::split::SplitSize;
+use crate::tokens::{format_count, Tokenizer};
+
+/// Share of the kept lines taken from the start of a file; the rest come from the end
+const HEAD_SHARE: f64 = 2.0 / 3.0;
+
+/// Per-file limits beyond which only the head and tail of a file are kept
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FileLimits {
+    pub max_size: Option<SplitSize>,
+    pub max_lines: Option<usize>,
+}
+
+/// Cuts `content` down to its first and last lines when it exceeds `limits`, with a marker line
+/// saying how many lines were left out. Returns `None` when the file is within the limits.
+pub fn truncate(content: &str, limits: FileLimits, tokenizer: &dyn Tokenizer) -> Option<String> {
+    let lines = content.split_inclusive('\n').collect::<Vec<_>>();
+    let measure = |text: &str| match limits.max_size {
+        Some(SplitSize::Tokens(_)) => tokenizer.count(text),
+        _ => text.len(),
+    };
+    let size_limit = match limits.max_size {
+        Some(SplitSize::Bytes(limit)) | Some(SplitSize::Tokens(limit)) => Some(limit),
+        None => None,
+    };
+
+    let over_lines = limits.max_lines.is_some_and(|max| lines.len() > max);
+    let over_size = size_limit.is_some_and(|limit| measure(content) > limit);
+    if !over_lines && !over_size {
+        return None;
+    }
+
+    let max_lines = limits.max_lines.unwrap_or(lines.len()).min(lines.len());
+    let mut head = (max_lines as f64 * HEAD_SHARE).ceil() as usize;
+    let mut tail = max_lines - head;
+    if let Some(limit) = size_limit {
+        let room = limit.saturating_sub(measure(&marker(lines.len())));
+        let head_room = (room as f64 * HEAD_SHARE) as usize;
+        head = head.min(lines_within(lines.iter(), head_room, &measure));
+        tail = tail.min(lines_within(lines.iter().rev(), room - head_room, &measure));
+    }
+    if head + tail >= lines.len() {
+        return None;
+    }
+
+    let mut truncated = lines[..head].concat();
+    truncated.push_str(&marker(lines.len() - head - tail));
+    truncated.push_str(&lines[lines.len() - tail..].concat());
+    Some(truncated)
+}
+
+/// The line standing in for the omitted middle of a file
+fn marker(omitted: usize) -> String {
+    let noun = if omitted == 1 { "line" } else { "lines" };
+    format!("… {} {} omitted …\n", format_count(omitted), noun)
+}
+
+/// Counts how many of `lines` fit in `room`, in order.
+fn lines_within<'a>(lines: impl Iterator<Item = &'a &'a str>, room: usize, measure: &dyn Fn(&str) -> usize) -> usize {
+    let mut used = 0;
+    lines
+        .take_while(|line| {
+            used += measure(line);
+            used <= room
+        })
+        .count()
+}
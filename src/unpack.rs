@@ -0,0 +1,79 @@
+use crate::encoding;
+use crate::error::{Result, JockeyError};
+use crate::output::parse_image;
+use std::path::Path;
+use tokio::fs;
+use colored::*;
+
+/// Recreates the files stored in an image under `into` (the current directory by default).
+pub async fn unpack(image: &Path, into: Option<&Path>, force: bool) -> Result<()> {
+    let target_dir = match into {
+        Some(dir) => dir.to_path_buf(),
+        None => std::env::current_dir().map_err(|e| {
+            JockeyError::Config(format!("Failed to get current directory: {}", e))
+        })?,
+    };
+
+    let input = fs::read_to_string(image).await.map_err(|e| {
+        JockeyError::Processing(format!("Failed to read image '{}': {}", image.display(), e))
+    })?;
+    let repo = parse_image(&input)?;
+    let paths = repo.relative_paths()?;
+
+    // Outlines are not the real file contents, so they are never written out
+    let entries = paths
+        .into_iter()
+        .zip(&repo.files)
+        .filter(|(_, file)| file.is_complete())
+        .collect::<Vec<_>>();
+    let skipped = repo.files.len() - entries.len();
+
+    // Refuse to clobber anything before writing the first file
+    if !force {
+        let existing = entries
+            .iter()
+            .map(|(path, _)| path)
+            .filter(|path| target_dir.join(path).exists())
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        if !existing.is_empty() {
+            return Err(JockeyError::Processing(format!(
+                "{} file(s) already exist in '{}' (use --force to overwrite): {}",
+                existing.len(),
+                target_dir.display(),
+                existing.join(", ")
+            )));
+        }
+    }
+
+    for (path, file) in &entries {
+        let destination = target_dir.join(path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await.map_err(|e| {
+                JockeyError::Processing(format!("Failed to create directory '{}': {}", parent.display(), e))
+            })?;
+        }
+        // Files decoded from a legacy encoding are written back in that encoding
+        let bytes = encoding::encode(&file.content, file.encoding.as_deref()).map_err(|e| {
+            JockeyError::Processing(format!("Failed to encode '{}': {}", destination.display(), e))
+        })?;
+        fs::write(&destination, bytes).await.map_err(|e| {
+            JockeyError::Processing(format!("Failed to write '{}': {}", destination.display(), e))
+        })?;
+    }
+
+    println!("\n{}", "Jockey image unpacked successfully!".green().bold());
+    println!("{} {} ({} files)",
+        "Location:".blue(),
+        target_dir.display().to_string().yellow(),
+        entries.len().to_string().cyan()
+    );
+    if skipped > 0 {
+        println!("{} {} incomplete or deleted file(s) were not written",
+            "Skipped:".blue(),
+            skipped.to_string().yellow()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use assert_fs::fixture::ChildPath;
+use assert_fs::prelude::*;
+use jockey_cli::cli::{Commands, Config, OutputFormat};
+use jockey_cli::output::{format_output, FileEntry, Repository};
+use jockey_cli::process;
+
+mod common;
+use common::project_temp_dir;
+
+#[tokio::test]
+async fn test_apply_writes_only_changed_files() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    root.child("changed.txt").write_str("old\n").unwrap();
+    root.child("same.txt").write_str("same\n").unwrap();
+
+    let edited = Repository::new(
+        "",
+        vec![
+            FileEntry::new("changed.txt", "new\n"),
+            FileEntry::new("same.txt", "same\n"),
+            FileEntry::new("nested/created.txt", "created\n"),
+        ],
+    );
+    let image = temp.path().join("edited.json");
+    std::fs::write(&image, format_output(edited, OutputFormat::Json).unwrap()).unwrap();
+    let same_modified = std::fs::metadata(temp.path().join("same.txt")).unwrap().modified().unwrap();
+
+    let config = Config {
+        command: Commands::Apply {
+            image,
+            path: Some(temp.path().to_string_lossy().into_owned()),
+            yes: true,
+        },
+    };
+    process(config).await.unwrap();
+
+    root.child("changed.txt").assert("new\n");
+    root.child("nested/created.txt").assert("created\n");
+    let same_after = std::fs::metadata(temp.path().join("same.txt")).unwrap().modified().unwrap();
+    assert_eq!(same_modified, same_after);
+}
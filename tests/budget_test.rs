@@ -0,0 +1,64 @@
+use jockey_cli::budget::{select, Candidate};
+use jockey_cli::output::FileEntry;
+use jockey_cli::tokens::Tokenizer;
+use std::time::{Duration, SystemTime};
+
+/// Counts whitespace-separated words, which keeps the expected numbers easy to follow
+struct WordTokenizer;
+
+impl Tokenizer for WordTokenizer {
+    fn name(&self) -> &'static str {
+        "words"
+    }
+
+    fn count(&self, text: &str) -> usize {
+        text.split_whitespace().count()
+    }
+}
+
+fn candidate(path: &str, content: &str, age_days: u64) -> Candidate {
+    let mut entry = FileEntry::new(path, content);
+    entry.tokens = WordTokenizer.count(content);
+    Candidate {
+        entry,
+        modified: SystemTime::now().checked_sub(Duration::from_secs(age_days * 24 * 60 * 60)),
+    }
+}
+
+fn body(words: usize) -> String {
+    vec!["word"; words].join(" ") + "\n"
+}
+
+#[test]
+fn test_budget_keeps_priority_files_and_lists_the_rest() {
+    let candidates = vec![
+        candidate("src/old_helpers.rs", &body(40), 90),
+        candidate("src/recent.rs", &body(40), 1),
+        candidate("README.md", &body(30), 90),
+        candidate("src/main.rs", &body(30), 90),
+    ];
+
+    // Room for three 30-40 word files plus their per-file overhead
+    let selection = select(candidates, "", 130, &WordTokenizer).unwrap();
+
+    let kept = selection.files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>();
+    assert_eq!(kept, vec!["src/recent.rs", "README.md", "src/main.rs"]);
+    assert_eq!(selection.omitted.len(), 1);
+    assert_eq!(selection.omitted[0].path, "src/old_helpers.rs");
+    assert_eq!(selection.omitted[0].tokens, 40);
+}
+
+#[test]
+fn test_budget_outlines_files_that_do_not_fit_whole() {
+    let mut source = String::from("pub fn handler() {\n");
+    source.push_str(&body(200));
+    source.push_str("}\n");
+    let candidates = vec![candidate("src/handler.rs", &source, 90)];
+
+    let selection = select(candidates, "", 50, &WordTokenizer).unwrap();
+
+    assert!(selection.omitted.is_empty());
+    assert!(selection.files[0].outlined);
+    assert!(!selection.files[0].is_complete());
+    assert_eq!(selection.files[0].content, "pub fn handler() {\n");
+}
@@ -0,0 +1,21 @@
+use jockey_cli::cli::PathStyle;
+use std::path::Path;
+
+#[test]
+fn test_path_style_parsing() {
+    assert_eq!("absolute".parse::<PathStyle>(), Ok(PathStyle::Absolute));
+    assert_eq!("relative".parse::<PathStyle>(), Ok(PathStyle::Relative));
+    assert_eq!("prefixed:app/".parse::<PathStyle>(), Ok(PathStyle::Prefixed("app".to_string())));
+    assert!("prefixed:".parse::<PathStyle>().is_err());
+    assert!("home".parse::<PathStyle>().is_err());
+}
+
+#[test]
+fn test_path_style_display() {
+    let root = Path::new("/Users/someone/project");
+    let file = root.join("src/main.rs");
+
+    assert_eq!(PathStyle::Relative.display(&file, root), "src/main.rs");
+    assert_eq!(PathStyle::Prefixed("app".to_string()).display(&file, root), "app/src/main.rs");
+    assert_eq!(PathStyle::Absolute.display(&file, root), "/Users/someone/project/src/main.rs");
+}
@@ -0,0 +1,50 @@
+// Shared helpers for the integration tests.
+
+use assert_fs::fixture::ChildPath;
+use assert_fs::prelude::*;
+use std::path::Path;
+use std::process::Command;
+
+/// Creates a scratch directory inside the crate so it sits under the detected project root.
+pub fn project_temp_dir() -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix("jockey-test")
+        .tempdir_in(env!("CARGO_MANIFEST_DIR"))
+        .unwrap()
+}
+
+// Only the crates that test git features use these
+#[allow(dead_code)]
+pub fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "commit.gpgsign=false"])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+/// A repository with a `main` branch and a `feature` branch that changes two files, plus one
+/// staged, one unstaged and one untracked change on top.
+#[allow(dead_code)]
+pub fn feature_branch(root: &ChildPath) {
+    root.child("src/main.rs").write_str("fn main() {}\n").unwrap();
+    root.child("src/lib.rs").write_str("pub fn lib() {}\n").unwrap();
+    root.child("README.md").write_str("# Project\n").unwrap();
+    git(root.path(), &["init", "-q", "-b", "main"]);
+    git(root.path(), &["add", "."]);
+    git(root.path(), &["commit", "-q", "-m", "initial"]);
+
+    git(root.path(), &["checkout", "-q", "-b", "feature"]);
+    root.child("src/lib.rs").write_str("pub fn lib() { todo!() }\n").unwrap();
+    root.child("src/new.rs").write_str("pub fn new() {}\n").unwrap();
+    git(root.path(), &["add", "."]);
+    git(root.path(), &["commit", "-q", "-m", "feature"]);
+
+    root.child("README.md").write_str("# Project\n\nStaged.\n").unwrap();
+    git(root.path(), &["add", "README.md"]);
+    root.child("src/main.rs").write_str("fn main() { println!(); }\n").unwrap();
+    root.child("notes.txt").write_str("untracked\n").unwrap();
+}
@@ -0,0 +1,148 @@
+use clap::{CommandFactory, FromArgMatches};
+use jockey_cli::cli::{Config, ConfigFile, OutputFormat};
+use jockey_cli::split::SplitSize;
+use jockey_cli::tree::{TreeAnnotation, TreeOrder};
+use std::path::{Path, PathBuf};
+
+const CONFIG: &str = r#"
+format = "xml"
+exclude = ["*.log", "tmp/"]
+max-tokens = 100000
+tree-annotate = ["size", "tokens"]
+
+[profile.review]
+format = "json"
+max-file-lines = 400
+split-size = "50000tokens"
+output = "context/review.json"
+
+[profile.small]
+max-tokens = 20000
+"#;
+
+/// Parses `args` and fills in the settings of `profile` from `CONFIG`.
+fn configure(args: &[&str], profile: Option<&str>) -> Config {
+    let matches = Config::command().try_get_matches_from(args).unwrap();
+    let mut config = Config::from_arg_matches(&matches).unwrap();
+    let settings = ConfigFile::parse(CONFIG).unwrap().resolve(profile).unwrap();
+    settings
+        .apply(&mut config.command, matches.subcommand_matches("generate").unwrap(), Path::new("/project"))
+        .unwrap();
+    config
+}
+
+#[test]
+fn test_base_settings_apply_without_profile() {
+    let config = configure(&["jockey", "generate"], None);
+
+    assert!(matches!(config.format(), OutputFormat::Xml));
+    assert_eq!(config.max_tokens(), Some(100000));
+    assert_eq!(config.tree_annotations(), vec![TreeAnnotation::Size, TreeAnnotation::Tokens]);
+    assert_eq!(config.exclude_patterns(), Some(&"*.log,tmp/".to_string()));
+    assert_eq!(config.file_limits().max_lines, None);
+}
+
+#[test]
+fn test_profile_overrides_base_settings() {
+    let config = configure(&["jockey", "generate"], Some("review"));
+
+    assert!(matches!(config.format(), OutputFormat::Json));
+    assert_eq!(config.max_tokens(), Some(100000));
+    assert_eq!(config.file_limits().max_lines, Some(400));
+    assert_eq!(config.split_size(), Some(SplitSize::Tokens(50000)));
+    assert_eq!(config.output_path(), Some(&PathBuf::from("/project/context/review.json")));
+
+    let config = configure(&["jockey", "generate"], Some("small"));
+    assert!(matches!(config.format(), OutputFormat::Xml));
+    assert_eq!(config.max_tokens(), Some(20000));
+}
+
+#[test]
+fn test_command_line_overrides_profile() {
+    let config = configure(
+        &["jockey", "generate", "--md", "--max-tokens", "5000", "--max-file-lines", "10", "--tree-order", "alphabetical"],
+        Some("review"),
+    );
+
+    assert!(matches!(config.format(), OutputFormat::Md));
+    assert_eq!(config.max_tokens(), Some(5000));
+    assert_eq!(config.file_limits().max_lines, Some(10));
+    assert_eq!(config.tree_order(), TreeOrder::Alphabetical);
+    assert_eq!(config.split_size(), Some(SplitSize::Tokens(50000)));
+}
+
+#[test]
+fn test_config_file_errors() {
+    let file = ConfigFile::parse(CONFIG).unwrap();
+    let err = file.resolve(Some("missing")).unwrap_err().to_string();
+    assert!(err.contains("review, small"), "{}", err);
+
+    // Typos are reported instead of silently ignored
+    assert!(ConfigFile::parse("max-token = 10").is_err());
+    assert!(ConfigFile::parse("[profile.review]\nformatt = \"md\"").is_err());
+
+    let matches = Config::command().try_get_matches_from(["jockey", "generate"]).unwrap();
+    let mut config = Config::from_arg_matches(&matches).unwrap();
+    let settings = ConfigFile::parse("format = \"pdf\"").unwrap().resolve(None).unwrap();
+    assert!(settings
+        .apply(&mut config.command, matches.subcommand_matches("generate").unwrap(), Path::new("/project"))
+        .is_err());
+}
+
+/// Parses `args` and applies the base settings of `file`.
+fn apply_file(args: &[&str], file: &str) -> jockey_cli::error::Result<Config> {
+    let matches = Config::command().try_get_matches_from(args).unwrap();
+    let mut config = Config::from_arg_matches(&matches).unwrap();
+    let settings = ConfigFile::parse(file).unwrap().resolve(None).unwrap();
+    settings.apply(&mut config.command, matches.subcommand_matches("generate").unwrap(), Path::new("/project"))?;
+    Ok(config)
+}
+
+#[test]
+fn test_conflicts_in_the_file_are_errors() {
+    // clap never sees the file, so its settings are checked once merged
+    let err = apply_file(&["jockey", "generate"], "stdout = true\noutput = \"image.md\"").unwrap_err().to_string();
+    assert!(err.contains("'output' and 'stdout'"), "{}", err);
+    assert!(apply_file(&["jockey", "generate"], "stdout = true\nsplit-size = \"50kb\"").is_err());
+    assert!(apply_file(&["jockey", "generate"], "since = \"main\"\nstaged = true").is_err());
+
+    // A change selection on the command line replaces the file's as a whole
+    let config = apply_file(&["jockey", "generate", "--since", "main"], "staged = true").unwrap();
+    assert!(config.changes().is_some());
+}
+
+#[test]
+fn test_command_line_destination_replaces_the_files() {
+    let config = apply_file(&["jockey", "generate", "--stdout"], "output = \"image.md\"\nsplit-size = \"50kb\"").unwrap();
+    assert!(config.to_stdout());
+    assert_eq!(config.output_path(), None);
+    assert_eq!(config.split_size(), None);
+
+    let config = apply_file(&["jockey", "generate", "--output", "image.md"], "stdout = true").unwrap();
+    assert!(!config.to_stdout());
+    assert_eq!(config.output_path(), Some(&PathBuf::from("image.md")));
+    let config = apply_file(&["jockey", "generate", "--split-size", "50kb"], "stdout = true").unwrap();
+    assert!(!config.to_stdout());
+}
+
+#[test]
+fn test_no_flags_turn_off_file_settings() {
+    let file = "stdout = true\nstaged = true\ngit-history = true\ninclude-generated = true\nparallel = true";
+    let config = apply_file(&["jockey", "generate"], file).unwrap();
+    assert!(config.to_stdout() && config.changes().is_some() && config.git_history().is_some());
+
+    let config = apply_file(
+        &["jockey", "generate", "--no-stdout", "--no-staged", "--no-git-history", "--no-include-generated", "--no-parallel"],
+        file,
+    )
+    .unwrap();
+    assert!(!config.to_stdout());
+    assert!(config.changes().is_none());
+    assert!(config.git_history().is_none());
+    assert!(!config.include_generated());
+    assert!(!config.is_parallel());
+
+    // The last of a flag and its `--no-` form wins on the command line
+    let config = apply_file(&["jockey", "generate", "--no-stdout", "--stdout"], file).unwrap();
+    assert!(config.to_stdout());
+}
@@ -1,39 +1,61 @@
+use assert_fs::fixture::ChildPath;
 use assert_fs::prelude::*;
 use predicates::prelude::*;
-use jockey_cli::{cli::{Config, Commands}, process};
+use jockey_cli::{cli::{Config, Commands, GenerateArgs}, process};
 use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+mod common;
+use common::project_temp_dir;
+
+// Every generate run writes into the shared `jockey-img/` directory at the project root,
+// so tests that touch it must not run concurrently.
+static OUTPUT_LOCK: Mutex<()> = Mutex::const_new(());
+
+fn read_single_image(output: &PathBuf) -> String {
+    let entries = std::fs::read_dir(output)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 1);
+    std::fs::read_to_string(entries[0].path()).unwrap()
+}
 
 #[tokio::test]
 async fn test_basic_markdown_generation() {
+    let _guard = OUTPUT_LOCK.lock().await;
+
     // Create a temporary directory with some test files
-    let temp = assert_fs::TempDir::new().unwrap();
-    
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+
     // Create a simple file structure
-    temp.child("file1.txt").write_str("Hello, World!").unwrap();
-    temp.child("dir1").create_dir_all().unwrap();
-    temp.child("dir1/file2.txt").write_str("Test content").unwrap();
-    
+    root.child("file1.txt").write_str("Hello, World!").unwrap();
+    root.child("dir1").create_dir_all().unwrap();
+    root.child("dir1/file2.txt").write_str("Test content").unwrap();
+
     // Create test configuration
     let config = Config {
-        command: Commands::Generate {
+        command: Commands::Generate(Box::new(GenerateArgs {
             path: Some(temp.path().to_string_lossy().into_owned()),
-            md: true,
-            json: false,
-            txt: false,
-            yaml: false,
-            exclude: None,
-            parallel: false,
-            verbose: false,
-        },
+            ..Default::default()
+        })),
     };
-    
+
     // Process the repository
     process(config).await.unwrap();
-    
+
     // Verify output file exists and contains expected content
     let output = PathBuf::from("jockey-img");
     assert!(output.exists());
-    
+    let image = read_single_image(&output);
+    assert!(predicate::str::contains("Hello, World!").eval(&image));
+    assert!(predicate::str::contains("Test content").eval(&image));
+
+    // Paths are relative to the processed directory
+    assert!(predicate::str::contains("## File: dir1/file2.txt").eval(&image));
+    assert!(!image.contains(&*temp.path().to_string_lossy()));
+
     // Cleanup
     std::fs::remove_dir_all(output).unwrap();
     temp.close().unwrap();
@@ -41,33 +63,119 @@ async fn test_basic_markdown_generation() {
 
 #[tokio::test]
 async fn test_exclude_patterns() {
-    let temp = assert_fs::TempDir::new().unwrap();
-    
+    let _guard = OUTPUT_LOCK.lock().await;
+
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+
     // Create files and directories
-    temp.child("include.txt").write_str("Include this").unwrap();
-    temp.child("exclude.tmp").write_str("Exclude this").unwrap();
-    temp.child("node_modules/test.js").create_dir_all().unwrap();
-    
+    root.child("include.txt").write_str("Include this").unwrap();
+    root.child("exclude.tmp").write_str("Exclude this").unwrap();
+    root.child("node_modules/test.js").create_dir_all().unwrap();
+
     let config = Config {
-        command: Commands::Generate {
+        command: Commands::Generate(Box::new(GenerateArgs {
             path: Some(temp.path().to_string_lossy().into_owned()),
-            md: true,
-            json: false,
-            txt: false,
-            yaml: false,
             exclude: Some("*.tmp,node_modules".to_string()),
-            parallel: false,
-            verbose: false,
-        },
+            ..Default::default()
+        })),
     };
-    
+
     process(config).await.unwrap();
-    
+
     // Verify output directory exists
     let output = PathBuf::from("jockey-img");
     assert!(output.exists());
-    
+    let image = read_single_image(&output);
+    assert!(predicate::str::contains("Include this").eval(&image));
+    assert!(predicate::str::contains("Exclude this").not().eval(&image));
+    assert!(!image.contains("exclude.tmp"));
+
     // Cleanup
     std::fs::remove_dir_all(output).unwrap();
     temp.close().unwrap();
-} 
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_explicit_output_path_numbers_parts() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    for i in 0..4 {
+        root.child(format!("src/file{}.txt", i)).write_str(&"x".repeat(600)).unwrap();
+    }
+    let image = temp.path().join("out/image.md");
+
+    let config = Config {
+        command: Commands::Generate(Box::new(GenerateArgs {
+            path: Some(temp.path().to_string_lossy().into_owned()),
+            split_size: Some("1500b".parse().unwrap()),
+            output: Some(image.clone()),
+            ..Default::default()
+        })),
+    };
+
+    process(config).await.unwrap();
+
+    assert!(!image.exists());
+    let first = std::fs::read_to_string(temp.path().join("out/image.part1.md")).unwrap();
+    assert!(first.contains("Part: 1 of"));
+    assert!(temp.path().join("out/image.part2.md").exists());
+}
+
+#[test]
+fn test_stdout_keeps_summary_off_the_image() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    root.child("src/main.rs").write_str("fn main() {}\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_jockey"))
+        .args(["generate", "--stdout"])
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stdout.starts_with("# Jockey Image"));
+    assert!(stdout.contains("fn main() {}"));
+    assert!(!stdout.contains("created successfully"));
+    assert!(stderr.contains("created successfully"));
+    assert!(!temp.path().join("jockey-img").exists());
+}
+
+#[test]
+fn test_profile_from_jockey_toml() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    root.child("src/main.rs").write_str("fn main() {}\n").unwrap();
+    root.child("jockey.toml")
+        .write_str("format = \"json\"\n\n[profile.review]\nformat = \"xml\"\n")
+        .unwrap();
+
+    let run = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_jockey"))
+            .arg("generate")
+            .args(args)
+            .arg("--stdout")
+            .current_dir(temp.path())
+            .output()
+            .unwrap();
+        (output.status.success(), String::from_utf8(output.stdout).unwrap())
+    };
+
+    let (success, image) = run(&[]);
+    assert!(success);
+    assert!(image.starts_with('{'));
+
+    let (success, image) = run(&["--profile", "review"]);
+    assert!(success);
+    assert!(image.contains("<document_content>"));
+
+    let (success, image) = run(&["--profile", "review", "--txt"]);
+    assert!(success);
+    assert!(!image.contains("<document_content>"));
+
+    let (success, _) = run(&["--profile", "missing"]);
+    assert!(!success);
+}
@@ -0,0 +1,48 @@
+use jockey_cli::cli::OutputFormat;
+use jockey_cli::output::{format_output, FileEntry, Repository};
+use jockey_cli::tokens::{default_tokenizer, format_count};
+
+fn sample_repo() -> Repository {
+    Repository::new(
+        "project\n└── a&b.rs\n",
+        vec![
+            FileEntry::new("a&b.rs", "let s = \"]]>\";\n```\nnot a fence\n"),
+            FileEntry::new("notes.txt", "<tag>"),
+        ],
+    )
+}
+
+#[test]
+fn test_xml_documents_are_indexed_and_escaped() {
+    let output = format_output(sample_repo(), OutputFormat::Xml).unwrap();
+
+    assert!(output.contains("<document index=\"1\" tokens=\"0\">\n<source>a&amp;b.rs</source>"));
+    assert!(output.contains("<document index=\"2\" tokens=\"0\">\n<source>notes.txt</source>"));
+    // `]]>` inside content must not terminate the CDATA section early
+    assert!(output.contains("let s = \"]]]]><![CDATA[>\";"));
+    assert!(output.contains("<document_content><![CDATA[<tag>]]></document_content>"));
+    assert_eq!(output.matches("<document ").count(), 2);
+}
+
+#[test]
+fn test_cl100k_token_counts() {
+    let tokenizer = default_tokenizer();
+    assert_eq!(tokenizer.name(), "cl100k");
+    assert_eq!(tokenizer.count(""), 0);
+    assert_eq!(tokenizer.count("hello world"), 2);
+    assert_eq!(format_count(1234567), "1,234,567");
+    assert_eq!(format_count(999), "999");
+}
+
+#[test]
+fn test_repository_totals_file_tokens() {
+    let mut small = FileEntry::new("a.rs", "fn a() {}");
+    small.tokens = 5;
+    let mut large = FileEntry::new("b.rs", "fn b() {}");
+    large.tokens = 1200;
+    let repo = Repository::new("project\n", vec![small, large]);
+    assert_eq!(repo.tokens, 1205);
+
+    let output = format_output(repo, OutputFormat::Md).unwrap();
+    assert!(output.contains("Tokens: 1,205"));
+}
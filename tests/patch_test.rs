@@ -0,0 +1,129 @@
+use assert_fs::fixture::ChildPath;
+use assert_fs::prelude::*;
+use jockey_cli::cli::{Commands, Config};
+use jockey_cli::process;
+use std::path::PathBuf;
+
+mod common;
+use common::project_temp_dir;
+
+fn patch_config(patch: PathBuf, dry_run: bool) -> Config {
+    Config {
+        command: Commands::Patch {
+            patches: vec![patch],
+            dry_run,
+        },
+    }
+}
+
+#[tokio::test]
+async fn test_patch_applies_fenced_diff_with_offset() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    let dir = temp.path().file_name().unwrap().to_string_lossy().into_owned();
+    root.child("lib.rs")
+        .write_str("// moved down by a header\n\nfn one() {\n    1\n}\n\nfn two() {\n    2\n}\n")
+        .unwrap();
+
+    // Header counts are wrong and the blank context line lost its space, as in real chat output
+    let response = format!(
+        "Here is the fix:\n\n```diff\n--- a/{dir}/lib.rs\n+++ b/{dir}/lib.rs\n@@ -1,4 +1,4 @@\n fn one() {{\n-    1\n+    10\n }}\n\n@@ -5,3 +5,4 @@\n fn two() {{\n-    2\n+    20\n+    // doubled\n }}\n```\n\nLet me know!\n"
+    );
+    let patch = temp.path().join("response.md");
+    std::fs::write(&patch, response).unwrap();
+
+    process(patch_config(patch.clone(), true)).await.unwrap();
+    root.child("lib.rs").assert(predicates::str::contains("    1\n"));
+
+    process(patch_config(patch, false)).await.unwrap();
+    root.child("lib.rs").assert(
+        "// moved down by a header\n\nfn one() {\n    10\n}\n\nfn two() {\n    20\n    // doubled\n}\n",
+    );
+}
+
+#[tokio::test]
+async fn test_patch_with_failing_hunk_leaves_file_unchanged() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    let dir = temp.path().file_name().unwrap().to_string_lossy().into_owned();
+    root.child("notes.txt").write_str("alpha\nbeta\ngamma\n").unwrap();
+
+    let diff = format!(
+        "--- {dir}/notes.txt\n+++ {dir}/notes.txt\n@@ -1,2 +1,2 @@\n alpha\n-beta\n+BETA\n@@ -3,1 +3,1 @@\n-delta\n+DELTA\n"
+    );
+    let patch = temp.path().join("change.diff");
+    std::fs::write(&patch, diff).unwrap();
+
+    assert!(process(patch_config(patch, false)).await.is_err());
+    root.child("notes.txt").assert("alpha\nbeta\ngamma\n");
+}
+
+#[tokio::test]
+async fn test_patch_creating_existing_file_is_a_conflict() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    let dir = temp.path().file_name().unwrap().to_string_lossy().into_owned();
+    root.child("notes.txt").write_str("keep me\n").unwrap();
+
+    let patch = temp.path().join("new.diff");
+    std::fs::write(&patch, format!("--- /dev/null\n+++ b/{dir}/notes.txt\n@@ -0,0 +1 @@\n+replacement\n")).unwrap();
+
+    assert!(process(patch_config(patch, false)).await.is_err());
+    root.child("notes.txt").assert("keep me\n");
+}
+
+#[tokio::test]
+async fn test_patch_writes_nothing_when_any_file_fails() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    let dir = temp.path().file_name().unwrap().to_string_lossy().into_owned();
+    root.child("a.txt").write_str("one\ntwo\n").unwrap();
+    root.child("b.txt").write_str("three\nfour\n").unwrap();
+
+    let diff = format!(
+        "--- a/{dir}/a.txt\n+++ b/{dir}/a.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+TWO\n\
+         --- a/{dir}/b.txt\n+++ b/{dir}/b.txt\n@@ -1,2 +1,2 @@\n three\n-five\n+FIVE\n"
+    );
+    let patch = temp.path().join("change.diff");
+    std::fs::write(&patch, diff).unwrap();
+
+    assert!(process(patch_config(patch, false)).await.is_err());
+    root.child("a.txt").assert("one\ntwo\n");
+    root.child("b.txt").assert("three\nfour\n");
+}
+
+#[tokio::test]
+async fn test_patch_hunk_lines_that_look_like_file_headers() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    let dir = temp.path().file_name().unwrap().to_string_lossy().into_owned();
+    root.child("query.sql").write_str("SELECT 1;\n-- old comment\nSELECT 2;\n").unwrap();
+
+    // The removed `-- ` line followed by an added `++ ` line reads like a file header
+    let diff = format!(
+        "--- a/{dir}/query.sql\n+++ b/{dir}/query.sql\n@@ -1,3 +1,3 @@\n SELECT 1;\n--- old comment\n+++ new comment\n SELECT 2;\n"
+    );
+    let patch = temp.path().join("change.diff");
+    std::fs::write(&patch, diff).unwrap();
+
+    process(patch_config(patch, false)).await.unwrap();
+    root.child("query.sql").assert("SELECT 1;\n++ new comment\nSELECT 2;\n");
+}
+
+#[tokio::test]
+async fn test_patch_keeps_file_whitespace_on_context_lines() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    let dir = temp.path().file_name().unwrap().to_string_lossy().into_owned();
+    root.child("Makefile").write_str("all:\n\techo one\n\techo two\n\techo three\n").unwrap();
+
+    // The tabs were turned into spaces on the way through a chat window
+    let diff = format!(
+        "--- a/{dir}/Makefile\n+++ b/{dir}/Makefile\n@@ -1,4 +1,4 @@\n all:\n     echo one\n-    echo two\n+\techo TWO\n     echo three\n"
+    );
+    let patch = temp.path().join("change.diff");
+    std::fs::write(&patch, diff).unwrap();
+
+    process(patch_config(patch, false)).await.unwrap();
+    root.child("Makefile").assert("all:\n\techo one\n\techo TWO\n\techo three\n");
+}
@@ -0,0 +1,176 @@
+use assert_fs::fixture::ChildPath;
+use assert_fs::prelude::*;
+use jockey_cli::output::parse_image;
+use jockey_cli::review::related_files;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod common;
+use common::{feature_branch, git, project_temp_dir};
+
+fn paths(root: &Path, paths: &[&str]) -> Vec<PathBuf> {
+    paths.iter().map(|path| root.join(path)).collect()
+}
+
+/// `feature_branch` plus a changed source with a test, and a deleted file.
+fn review_branch(root: &ChildPath) {
+    root.child("src/parser.rs").write_str("pub fn parse() {}\n").unwrap();
+    root.child("tests/parser_test.rs").write_str("#[test]\nfn parses() {}\n").unwrap();
+    root.child("src/gone.rs").write_str("pub fn gone() {}\n").unwrap();
+    feature_branch(root);
+    root.child("src/parser.rs").write_str("pub fn parse() -> bool { true }\n").unwrap();
+    git(root.path(), &["rm", "-q", "src/gone.rs"]);
+}
+
+fn jockey(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_jockey")).args(args).current_dir(dir).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Review image of the changes against `main`, in the format selected by `format_flag`.
+fn review_image(dir: &Path, format_flag: &str) -> String {
+    jockey(dir, &["review", "main", format_flag, "--stdout"])
+}
+
+#[test]
+fn test_related_files_pair_sources_and_tests() {
+    // The project itself living under a `test` directory must not make every file a test
+    let root = Path::new("/home/someone/test/project");
+    let files = paths(root, &[
+        "src/tree.rs",
+        "src/tree/render.rs",
+        "src/lib.rs",
+        "src/parser.ts",
+        "src/parser.spec.ts",
+        "src/latest.rs",
+        "app/models/user.py",
+        "tests/tree_test.rs",
+        "tests/lib.rs",
+        "tests/test_user.py",
+        "src/main/java/Cache.java",
+        "src/test/java/CacheTest.java",
+    ]);
+    let changed = paths(root, &["src/tree.rs", "src/lib.rs", "src/parser.ts", "tests/test_user.py", "src/main/java/Cache.java"]);
+
+    assert_eq!(
+        related_files(root, &changed, &files),
+        paths(root, &[
+            "src/parser.spec.ts",
+            "app/models/user.py",
+            "tests/tree_test.rs",
+            "src/test/java/CacheTest.java",
+        ])
+    );
+}
+
+#[test]
+fn test_related_files_skip_changed_and_unrelated_files() {
+    let root = Path::new("/project");
+    let files = paths(root, &["src/tree.rs", "tests/tree_test.rs", "src/latest.rs", "src/contest.rs"]);
+    let changed = paths(root, &["src/tree.rs", "tests/tree_test.rs"]);
+
+    // Both halves changed, so there is nothing left to add; `latest`/`contest` are not tests
+    assert!(related_files(root, &changed, &files).is_empty());
+    assert!(related_files(root, &paths(root, &["src/latest.rs"]), &files).is_empty());
+}
+
+#[test]
+fn test_review_image_has_diffs_and_related_files() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    review_branch(&root);
+
+    let repo = parse_image(&review_image(temp.path(), "--json")).unwrap();
+    let files = repo.files.iter().map(|f| (f.path.as_str(), f.diff.is_some(), f.related)).collect::<Vec<_>>();
+    // Changes in tree order, then deleted files, then related files
+    assert_eq!(
+        files,
+        [
+            ("src/lib.rs", true, false),
+            ("src/main.rs", true, false),
+            ("src/new.rs", true, false),
+            ("src/parser.rs", true, false),
+            ("notes.txt", true, false),
+            ("README.md", true, false),
+            ("src/gone.rs", true, false),
+            ("tests/parser_test.rs", false, true),
+        ]
+    );
+    let lib = &repo.files[0];
+    assert_eq!(lib.content, "pub fn lib() { todo!() }\n");
+    assert!(lib.diff.as_deref().unwrap().contains("-pub fn lib() {}\n+pub fn lib() { todo!() }"));
+    assert!(repo.files[4].diff.as_deref().unwrap().starts_with("--- /dev/null\n+++ b/notes.txt"));
+    assert!(repo.files[6].diff.as_deref().unwrap().contains("-pub fn gone() {}"));
+    assert!(repo.files[6].deleted);
+    assert_eq!(repo.files[7].content, "#[test]\nfn parses() {}\n");
+    // The tree only lists the files in the image
+    assert!(repo.tree.contains("parser_test.rs"));
+    assert!(!repo.tree.contains("gone.rs"));
+}
+
+#[test]
+fn test_review_image_marks_deleted_and_untracked_files_in_every_format() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    review_branch(&root);
+
+    for flag in ["--md", "--txt", "--json", "--yaml", "--xml"] {
+        let repo = parse_image(&review_image(temp.path(), flag)).unwrap();
+
+        let gone = repo.files.iter().find(|f| f.path == "src/gone.rs").unwrap();
+        assert!(gone.deleted, "{}", flag);
+        assert!(!gone.is_complete(), "{}", flag);
+        assert!(gone.diff.as_deref().unwrap().contains("-pub fn gone() {}"), "{}", flag);
+
+        // Untracked files are new in their entirety
+        let notes = repo.files.iter().find(|f| f.path == "notes.txt").unwrap();
+        assert!(!notes.deleted, "{}", flag);
+        assert_eq!(notes.content, "untracked\n", "{}", flag);
+        assert!(notes.diff.as_deref().unwrap().starts_with("--- /dev/null\n+++ b/notes.txt"), "{}", flag);
+        assert!(notes.diff.as_deref().unwrap().contains("+untracked"), "{}", flag);
+    }
+}
+
+#[test]
+fn test_deleted_files_in_review_image_are_not_recreated() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    review_branch(&root);
+    let images = tempfile::tempdir().unwrap();
+    let image = images.path().join("review.md");
+    std::fs::write(&image, review_image(temp.path(), "--md")).unwrap();
+
+    let output = jockey(temp.path(), &["apply", image.to_str().unwrap(), "--yes"]);
+    assert!(output.contains("src/gone.rs (deleted in the image)"), "{}", output);
+    root.child("src/gone.rs").assert(predicates::path::missing());
+    root.child("src/parser.rs").assert("pub fn parse() -> bool { true }\n");
+
+    let into = images.path().join("unpacked");
+    jockey(temp.path(), &["unpack", image.to_str().unwrap(), "--into", into.to_str().unwrap()]);
+    assert!(into.join("notes.txt").exists());
+    assert!(!into.join("src/gone.rs").exists());
+}
+
+#[test]
+fn test_review_leaves_out_deleted_files_that_ignore_rules_exclude() {
+    let temp = project_temp_dir();
+    let root = ChildPath::new(temp.path());
+    root.child("src/kept.rs").write_str("pub fn kept() {}\n").unwrap();
+    root.child("target/debug/build.txt").write_str("build output\n").unwrap();
+    root.child("fixtures/data.json").write_str("{}\n").unwrap();
+    root.child("src/generated.rs").write_str("// force-added\n").unwrap();
+    root.child(".jockeyignore").write_str("fixtures/\n").unwrap();
+    root.child(".gitignore").write_str("src/generated.rs\n").unwrap();
+    git(root.path(), &["init", "-q", "-b", "main"]);
+    git(root.path(), &["add", "-f", "."]);
+    git(root.path(), &["commit", "-q", "-m", "initial"]);
+    for file in ["src/kept.rs", "target/debug/build.txt", "fixtures/data.json", "src/generated.rs"] {
+        git(root.path(), &["rm", "-q", file]);
+    }
+
+    let repo = parse_image(&review_image(temp.path(), "--json")).unwrap();
+    let files = repo.files.iter().map(|f| (f.path.as_str(), f.deleted)).collect::<Vec<_>>();
+    // Default ignores, .jockeyignore and .gitignore apply as they do to changed files
+    assert_eq!(files, [("src/kept.rs", true)]);
+}
@@ -0,0 +1,88 @@
+use jockey_cli::cli::OutputFormat;
+use jockey_cli::output::{format_output, FileEntry, Repository, SkipReason, SkippedFile};
+use jockey_cli::encoding;
+use jockey_cli::sniff::{is_binary, read_text, Text};
+
+#[test]
+fn test_binary_sniffing() {
+    assert!(is_binary(b"\x89PNG\r\n\x1a\n"));
+    assert!(is_binary(b"%PDF-1.7\n"));
+    assert!(is_binary(b"text with a \0 in it"));
+    assert!(is_binary(&[0x01, 0x02, 0x03, b'a', 0x04, 0x05]));
+    assert!(!is_binary(b""));
+    assert!(!is_binary(b"fn main() {\n\tprintln!(\"\x1b[1mhi\x1b[0m\");\r\n}\n"));
+    assert!(!is_binary("naïve café ✓\n".as_bytes()));
+}
+
+#[test]
+fn test_read_text_reports_reasons() {
+    let dir = tempfile::tempdir().unwrap();
+    let text = dir.path().join("a.txt");
+    let binary = dir.path().join("b.bin");
+    let broken_utf16 = dir.path().join("c.txt");
+    std::fs::write(&text, "hello\n").unwrap();
+    std::fs::write(&binary, b"\x7fELF\x02\x01\x01").unwrap();
+    std::fs::write(&broken_utf16, b"\xff\xfeh\0i").unwrap();
+
+    assert_eq!(read_text(&text), Ok(Text { content: "hello\n".to_string(), encoding: None }));
+    assert_eq!(read_text(&binary), Err(SkipReason::Binary));
+    assert_eq!(read_text(&broken_utf16), Err(SkipReason::NonUtf8));
+    assert_eq!(read_text(&dir.path().join("missing")), Err(SkipReason::Unreadable));
+}
+
+#[test]
+fn test_legacy_encodings_are_decoded_and_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    let cases: [(&str, Vec<u8>, &str, &str); 4] = [
+        (
+            "utf16le.txt",
+            encoding::encode("héllo wörld\n", Some("UTF-16LE")).unwrap(),
+            "héllo wörld\n",
+            "UTF-16LE",
+        ),
+        (
+            "utf16be.txt",
+            encoding::encode("héllo wörld\n", Some("UTF-16BE")).unwrap(),
+            "héllo wörld\n",
+            "UTF-16BE",
+        ),
+        (
+            "unit.pas",
+            b"// D\xe9j\xe0 vu: caf\xe9 cr\xe8me br\xfbl\xe9e, \x93quoted\x94 \x96 na\xefve r\xe9sum\xe9\n".to_vec(),
+            "// Déjà vu: café crème brûlée, \u{201c}quoted\u{201d} \u{2013} naïve résumé\n",
+            "windows-1252",
+        ),
+        (
+            "sjis.cpp",
+            b"// \x93\xfa\x96\x7b\x8c\xea\x82\xcc\x83\x52\x83\x81\x83\x93\x83\x67\x82\xc5\x82\xb7\x81\x42\n".to_vec(),
+            "// 日本語のコメントです。\n",
+            "Shift_JIS",
+        ),
+    ];
+
+    for (name, bytes, content, expected) in cases {
+        let path = dir.path().join(name);
+        std::fs::write(&path, &bytes).unwrap();
+        let text = read_text(&path).unwrap();
+        assert_eq!(text.content, content, "{}", name);
+        assert_eq!(text.encoding, Some(expected), "{}", name);
+        // Writing back restores the original bytes
+        assert_eq!(encoding::encode(&text.content, text.encoding).unwrap(), bytes, "{}", name);
+    }
+}
+
+#[test]
+fn test_skipped_files_are_listed_in_the_image() {
+    let mut repo = Repository::new("project\n├── logo.png\n└── a.rs\n", vec![FileEntry::new("a.rs", "fn a() {}\n")]);
+    repo.skipped = vec![SkippedFile { path: "logo.png".to_string(), reason: SkipReason::Binary }];
+
+    let markdown = format_output(repo.clone(), OutputFormat::Md).unwrap();
+    assert!(markdown.contains("## Skipped Files"));
+    assert!(markdown.contains("- logo.png (binary)"));
+
+    let xml = format_output(repo.clone(), OutputFormat::Xml).unwrap();
+    assert!(xml.contains("<file reason=\"binary\">logo.png</file>"));
+
+    let json = format_output(repo, OutputFormat::Json).unwrap();
+    assert!(json.contains("\"reason\": \"binary\""));
+}
@@ -0,0 +1,159 @@
+use jockey_cli::cli::OutputFormat;
+use jockey_cli::output::{format_output, parse_image, write_output, FileEntry, FileHistory, OmittedFile, Repository, SkipReason, SkippedFile};
+use jockey_cli::split::{split, SplitSize};
+use jockey_cli::tokens::default_tokenizer;
+
+#[test]
+fn test_split_size_parsing() {
+    assert_eq!("500kb".parse::<SplitSize>(), Ok(SplitSize::Bytes(500 * 1024)));
+    assert_eq!("2MB".parse::<SplitSize>(), Ok(SplitSize::Bytes(2 * 1024 * 1024)));
+    assert_eq!("80000".parse::<SplitSize>(), Ok(SplitSize::Bytes(80000)));
+    assert_eq!("50000tokens".parse::<SplitSize>(), Ok(SplitSize::Tokens(50000)));
+    assert_eq!("100kt".parse::<SplitSize>(), Ok(SplitSize::Tokens(100_000)));
+    assert!("12 parsecs".parse::<SplitSize>().is_err());
+    assert!("0kb".parse::<SplitSize>().is_err());
+}
+
+#[test]
+fn test_split_keeps_files_whole_and_repeats_tree() {
+    let files = (0..6)
+        .map(|i| FileEntry::new(format!("src/file{}.rs", i), "x".repeat(300) + "\n"))
+        .collect::<Vec<_>>();
+    let repo = Repository::new("project\n└── src\n", files);
+
+    // Room for two 300-byte files per part once the header, tree and footer are taken out
+    let parts = split(repo, SplitSize::Bytes(1100), OutputFormat::Md, default_tokenizer()).unwrap();
+
+    assert_eq!(parts.len(), 3);
+    for (i, part) in parts.iter().enumerate() {
+        assert!(format_output(part.clone(), OutputFormat::Md).unwrap().len() <= 1100);
+        assert_eq!(part.tree, "project\n└── src\n");
+        assert_eq!(part.part.map(|p| (p.index, p.count)), Some((i + 1, 3)));
+        assert_eq!(part.files.len(), 2);
+        assert!(part.files.iter().all(|f| f.is_complete()));
+    }
+}
+
+#[test]
+fn test_split_chunks_oversized_file_and_marks_chunks() {
+    let content = (0..200).map(|i| format!("line {:03}\n", i)).collect::<String>();
+    let repo = Repository::new("project\n", vec![FileEntry::new("big.txt", content.clone())]);
+
+    let parts = split(repo, SplitSize::Bytes(1024), OutputFormat::Md, default_tokenizer()).unwrap();
+
+    assert!(parts.len() > 1);
+    let mut rebuilt = String::new();
+    for part in &parts {
+        let image = format_output(part.clone(), OutputFormat::Md).unwrap();
+        assert!(image.contains(&format!("Part: {} of {}", part.part.unwrap().index, parts.len())));
+        let parsed = parse_image(&image).unwrap();
+        for file in parsed.files {
+            assert!(!file.is_complete());
+            assert_eq!(file.chunk.unwrap().count, parts.len());
+            rebuilt.push_str(&file.content);
+        }
+    }
+    assert_eq!(rebuilt, content);
+}
+
+#[test]
+fn test_split_streams_chunks_of_files_on_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.txt");
+    let content = (0..200).map(|i| format!("line {:03}\n", i)).collect::<String>();
+    std::fs::write(&path, &content).unwrap();
+    let entry = FileEntry::on_disk("big.txt", &path, content.len());
+    let repo = Repository::new("project\n", vec![entry]);
+
+    let parts = split(repo, SplitSize::Bytes(1024), OutputFormat::Md, default_tokenizer()).unwrap();
+
+    assert!(parts.len() > 1);
+    let mut rebuilt = String::new();
+    for part in &parts {
+        // Pieces are still byte ranges of the file until they are written
+        assert!(part.files.iter().all(|f| f.content.is_empty() && f.source.is_some()));
+        let mut image = Vec::new();
+        write_output(&mut image, part, OutputFormat::Json).unwrap();
+        let parsed = parse_image(&String::from_utf8(image).unwrap()).unwrap();
+        for file in parsed.files {
+            rebuilt.push_str(&file.content);
+        }
+    }
+    assert_eq!(rebuilt, content);
+}
+
+#[test]
+fn test_split_chunks_keep_the_history_caption() {
+    let content = (0..200).map(|i| format!("line {:03}\n", i)).collect::<String>();
+    let mut entry = FileEntry::new("big.txt", content);
+    entry.history = Some(FileHistory {
+        commit: "1a2b3c4".to_string(),
+        date: "2024-05-01".to_string(),
+        subject: "Fix parser".to_string(),
+        churn: 3,
+        window: "1 month".to_string(),
+    });
+    let repo = Repository::new("project\n", vec![entry.clone()]);
+
+    let parts = split(repo, SplitSize::Bytes(1024), OutputFormat::Md, default_tokenizer()).unwrap();
+
+    assert!(parts.len() > 1);
+    assert!(parts.iter().flat_map(|part| &part.files).all(|file| file.history == entry.history));
+}
+
+#[test]
+fn test_split_chunks_keep_the_file_encoding() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("legacy.txt");
+    let content = (0..200).map(|i| format!("café {:03}\n", i)).collect::<String>();
+    // Windows-1252 stores é as the single byte 0xE9
+    let bytes = content.replace('é', "\u{1}").bytes().map(|b| if b == 1 { 0xE9 } else { b }).collect::<Vec<_>>();
+    std::fs::write(&path, &bytes).unwrap();
+    let mut entry = FileEntry::on_disk("legacy.txt", &path, content.len());
+    entry.encoding = Some("windows-1252".to_string());
+    let repo = Repository::new("project\n", vec![entry]);
+
+    let parts = split(repo, SplitSize::Bytes(1024), OutputFormat::Md, default_tokenizer()).unwrap();
+
+    assert!(parts.len() > 1);
+    let rebuilt = parts
+        .iter()
+        .flat_map(|part| &part.files)
+        .map(|file| {
+            assert_eq!(file.encoding.as_deref(), Some("windows-1252"));
+            file.load().unwrap().content.clone()
+        })
+        .collect::<String>();
+    assert_eq!(rebuilt, content);
+}
+
+#[test]
+fn test_split_parts_fit_the_limit_in_every_format() {
+    // Quotes and newlines are escaped in JSON and indented in YAML, so they grow when rendered
+    let content = (0..120).map(|i| format!("    let s{:03} = \"{}\";\n", i, "q".repeat(i % 40))).collect::<String>();
+    let mut files = vec![FileEntry::new("src/big.rs", content)];
+    files.extend((0..8).map(|i| FileEntry::new(format!("src/small{}.rs", i), "fn f() {}\n".repeat(20))));
+    let mut repo = Repository::new("project\n└── src\n", files);
+    repo.omitted = (0..10).map(|i| OmittedFile { path: format!("src/omitted/file{:02}.rs", i), tokens: 1234 }).collect();
+    repo.skipped = (0..10).map(|i| SkippedFile { path: format!("assets/image{:02}.png", i), reason: SkipReason::Binary }).collect();
+
+    for format in [OutputFormat::Md, OutputFormat::Txt, OutputFormat::Json, OutputFormat::Yaml, OutputFormat::Xml] {
+        for limit in [SplitSize::Bytes(2048), SplitSize::Tokens(600)] {
+            let parts = split(repo.clone(), limit, format.clone(), default_tokenizer()).unwrap();
+            assert!(parts.len() > 1);
+            for part in &parts {
+                let image = format_output(part.clone(), format.clone()).unwrap();
+                let size = match limit {
+                    SplitSize::Bytes(_) => image.len(),
+                    SplitSize::Tokens(_) => default_tokenizer().count(&image),
+                };
+                let max = match limit {
+                    SplitSize::Bytes(max) | SplitSize::Tokens(max) => max,
+                };
+                assert!(size <= max, "{:?} part {:?} is {} for a limit of {:?}", format, part.part, size, limit);
+            }
+            let last = parts.last().unwrap();
+            assert_eq!((last.omitted.len(), last.skipped.len()), (10, 10));
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use jockey_cli::tree::{FileStats, RenderOptions, TreeAnnotation, TreeBuilder, TreeOrder};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Creates a small fixture project under `<tempdir>/fixture`.
+fn fixture() -> (tempfile::TempDir, std::path::PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("fixture");
+    for file in [
+        "README.md",
+        "Cargo.toml",
+        "src/main.rs",
+        "src/lib.rs",
+        "src/parser/mod.rs",
+        "src/parser/Lexer.rs",
+        "examples/basic_usage.rs",
+        "zeta.txt",
+        "alpha.txt",
+    ] {
+        let path = root.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "x\n").unwrap();
+    }
+    std::fs::create_dir_all(root.join("empty")).unwrap();
+    (dir, root)
+}
+
+fn relative(files: &[std::path::PathBuf], root: &Path) -> Vec<String> {
+    files
+        .iter()
+        .map(|f| f.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+        .collect()
+}
+
+#[test]
+fn test_dirs_first_tree() {
+    let (_dir, root) = fixture();
+    let (tree, files) = TreeBuilder::process_directory(&root, None, &[], TreeOrder::DirsFirst, false).unwrap();
+
+    assert_eq!(
+        tree,
+        "fixture
+├── empty
+├── examples
+│   └── basic_usage.rs
+├── src
+│   ├── parser
+│   │   ├── Lexer.rs
+│   │   └── mod.rs
+│   ├── lib.rs
+│   └── main.rs
+├── alpha.txt
+├── Cargo.toml
+├── README.md
+└── zeta.txt
+"
+    );
+    assert_eq!(
+        relative(&files, &root),
+        [
+            "examples/basic_usage.rs",
+            "src/parser/Lexer.rs",
+            "src/parser/mod.rs",
+            "src/lib.rs",
+            "src/main.rs",
+            "alpha.txt",
+            "Cargo.toml",
+            "README.md",
+            "zeta.txt",
+        ]
+    );
+}
+
+#[test]
+fn test_alphabetical_tree() {
+    let (_dir, root) = fixture();
+    let (tree, _) = TreeBuilder::process_directory(&root, None, &[], TreeOrder::Alphabetical, false).unwrap();
+
+    assert_eq!(
+        tree,
+        "fixture
+├── alpha.txt
+├── Cargo.toml
+├── empty
+├── examples
+│   └── basic_usage.rs
+├── README.md
+├── src
+│   ├── lib.rs
+│   ├── main.rs
+│   └── parser
+│       ├── Lexer.rs
+│       └── mod.rs
+└── zeta.txt
+"
+    );
+}
+
+#[test]
+fn test_tree_is_stable_across_runs() {
+    let (_dir, root) = fixture();
+    let first = TreeBuilder::process_directory(&root, None, &[], TreeOrder::DirsFirst, false).unwrap();
+    for _ in 0..5 {
+        assert_eq!(TreeBuilder::process_directory(&root, None, &[], TreeOrder::DirsFirst, false).unwrap(), first);
+    }
+}
+
+#[test]
+fn test_annotated_tree_rolls_up_directory_totals() {
+    let (_dir, root) = fixture();
+    let tree = TreeBuilder::walk(&root, None, &[], TreeOrder::DirsFirst, false).unwrap();
+    let mut stats = HashMap::new();
+    for (i, file) in tree.files().into_iter().enumerate() {
+        let text = !file.ends_with("zeta.txt");
+        stats.insert(
+            file,
+            FileStats {
+                size: 1000,
+                lines: text.then_some(10),
+                tokens: text.then_some(400 * (i + 1)),
+            },
+        );
+    }
+
+    let rendered = tree.build_with(&RenderOptions {
+        annotations: vec![TreeAnnotation::Size, TreeAnnotation::Lines, TreeAnnotation::Tokens],
+        stats,
+        ..RenderOptions::default()
+    });
+
+    assert_eq!(
+        rendered,
+        "fixture/ (9 files, 8.79 KB, 80 lines, 14.4k tokens)
+├── empty/ (0 files, 0 bytes, 0 lines, 0 tokens)
+├── examples/ (1 file, 1000 bytes, 10 lines, 400 tokens)
+│   └── basic_usage.rs (1000 bytes, 10 lines, 400 tokens)
+├── src/ (4 files, 3.91 KB, 40 lines, 5.6k tokens)
+│   ├── parser/ (2 files, 1.95 KB, 20 lines, 2.0k tokens)
+│   │   ├── Lexer.rs (1000 bytes, 10 lines, 800 tokens)
+│   │   └── mod.rs (1000 bytes, 10 lines, 1.2k tokens)
+│   ├── lib.rs (1000 bytes, 10 lines, 1.6k tokens)
+│   └── main.rs (1000 bytes, 10 lines, 2.0k tokens)
+├── alpha.txt (1000 bytes, 10 lines, 2.4k tokens)
+├── Cargo.toml (1000 bytes, 10 lines, 2.8k tokens)
+├── README.md (1000 bytes, 10 lines, 3.2k tokens)
+└── zeta.txt (1000 bytes)
+"
+    );
+}
+
+#[test]
+fn test_annotated_tree_counts_a_single_line_in_the_singular() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("fixture");
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::write(root.join("src/lib.rs"), "x\n").unwrap();
+    let tree = TreeBuilder::walk(&root, None, &[], TreeOrder::DirsFirst, false).unwrap();
+    let stats = HashMap::from([(root.join("src/lib.rs"), FileStats { size: 2, lines: Some(1), tokens: Some(1) })]);
+
+    let rendered = tree.build_with(&RenderOptions {
+        annotations: vec![TreeAnnotation::Lines, TreeAnnotation::Tokens],
+        stats,
+        ..RenderOptions::default()
+    });
+
+    assert_eq!(
+        rendered,
+        "fixture/ (1 file, 1 line, 1 token)
+└── src/ (1 file, 1 line, 1 token)
+    └── lib.rs (1 line, 1 token)
+"
+    );
+}
+
+#[test]
+fn test_depth_limit_and_wide_directories_collapse() {
+    let (_dir, root) = fixture();
+    let tree = TreeBuilder::walk(&root, None, &[], TreeOrder::DirsFirst, false).unwrap();
+
+    let shallow = tree.build_with(&RenderOptions { max_depth: Some(1), ..RenderOptions::default() });
+    assert_eq!(
+        shallow,
+        "fixture
+├── empty
+├── examples/ (1 file collapsed)
+├── src/ (4 files collapsed)
+├── alpha.txt
+├── Cargo.toml
+├── README.md
+└── zeta.txt
+"
+    );
+
+    let narrow = tree.build_with(&RenderOptions { collapse_over: Some(2), ..RenderOptions::default() });
+    assert_eq!(narrow, "fixture/ (9 files collapsed)\n");
+
+    let medium = tree.build_with(&RenderOptions { collapse_over: Some(7), max_depth: Some(2), ..RenderOptions::default() });
+    assert!(medium.contains("├── src\n│   ├── parser/ (2 files collapsed)\n│   ├── lib.rs\n"));
+
+    // Collapsing never changes which files are included
+    assert_eq!(tree.files().len(), 9);
+}
+
+#[test]
+fn test_include_patterns_keep_only_matching_files() {
+    let (_dir, root) = fixture();
+    let includes = ["src/**/*.rs".to_string(), "Cargo.toml".to_string()];
+    let (tree, files) = TreeBuilder::process_directory(&root, None, &includes, TreeOrder::DirsFirst, false).unwrap();
+
+    assert_eq!(
+        tree,
+        "fixture
+├── src
+│   ├── parser
+│   │   ├── Lexer.rs
+│   │   └── mod.rs
+│   ├── lib.rs
+│   └── main.rs
+└── Cargo.toml
+"
+    );
+    assert_eq!(files.len(), 5);
+
+    // A directory pattern keeps everything below it
+    let (_, files) = TreeBuilder::process_directory(&root, None, &["src/parser".to_string()], TreeOrder::DirsFirst, false).unwrap();
+    assert_eq!(relative(&files, &root), ["src/parser/Lexer.rs", "src/parser/mod.rs"]);
+}
+
+#[test]
+fn test_exclude_patterns_use_gitignore_syntax() {
+    let (_dir, root) = fixture();
+    std::fs::write(root.join("debug.log"), "noise\n").unwrap();
+    let excludes = "examples, src/parser/*.rs".to_string();
+    let (_, files) = TreeBuilder::process_directory(&root, Some(&excludes), &[], TreeOrder::DirsFirst, false).unwrap();
+
+    // `*.log` is excluded by default
+    assert_eq!(
+        relative(&files, &root),
+        ["src/lib.rs", "src/main.rs", "alpha.txt", "Cargo.toml", "README.md", "zeta.txt"]
+    );
+}
+
+#[test]
+fn test_jockeyignore_files_are_honored_per_directory() {
+    let (_dir, root) = fixture();
+    std::fs::write(root.join(".jockeyignore"), "examples/\n*.txt\n").unwrap();
+    std::fs::write(root.join("src/.jockeyignore"), "parser/Lexer.rs\n").unwrap();
+    let (tree, _) = TreeBuilder::process_directory(&root, None, &[], TreeOrder::DirsFirst, false).unwrap();
+
+    assert_eq!(
+        tree,
+        "fixture
+├── empty
+├── src
+│   ├── parser
+│   │   └── mod.rs
+│   ├── lib.rs
+│   └── main.rs
+├── Cargo.toml
+└── README.md
+"
+    );
+}
+
+#[test]
+fn test_gitattributes_markers_keep_files_out() {
+    let (_dir, root) = fixture();
+    for file in ["gen/schema.rs", "gen/api.rs", "assets/logo.svg", "src/parser/table.rs"] {
+        std::fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+        std::fs::write(root.join(file), "x\n").unwrap();
+    }
+    std::fs::write(
+        root.join(".gitattributes"),
+        "# Generated code\ngen/** linguist-generated=true\n*.svg -diff\nsrc/parser/*.rs linguist-generated\n*.md text eol=lf\n",
+    )
+    .unwrap();
+    // Deeper files override, and later lines override earlier ones
+    std::fs::write(root.join("src/.gitattributes"), "parser/mod.rs -linguist-generated\n").unwrap();
+    std::fs::write(root.join("src/parser/.gitattributes"), "Lexer.rs linguist-generated=false\nLexer.rs linguist-vendored\n").unwrap();
+
+    let tree = TreeBuilder::walk(&root, None, &[], TreeOrder::DirsFirst, false).unwrap();
+    assert_eq!(
+        tree.build(),
+        "fixture
+├── assets/ (1 file, -diff)
+├── empty
+├── examples
+│   └── basic_usage.rs
+├── gen/ (2 files, linguist-generated)
+├── src
+│   ├── parser
+│   │   ├── Lexer.rs (linguist-vendored)
+│   │   ├── mod.rs
+│   │   └── table.rs (linguist-generated)
+│   ├── lib.rs
+│   └── main.rs
+├── alpha.txt
+├── Cargo.toml
+├── README.md
+└── zeta.txt
+"
+    );
+    assert_eq!(tree.marked_count(), 5);
+    assert_eq!(
+        relative(&tree.files(), &root),
+        ["examples/basic_usage.rs", "src/parser/mod.rs", "src/lib.rs", "src/main.rs", "alpha.txt", "Cargo.toml", "README.md", "zeta.txt"]
+    );
+
+    let tree = TreeBuilder::walk(&root, None, &[], TreeOrder::DirsFirst, true).unwrap();
+    assert_eq!(tree.marked_count(), 0);
+    assert_eq!(tree.files().len(), 13);
+}
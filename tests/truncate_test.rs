@@ -0,0 +1,47 @@
+use jockey_cli::cli::OutputFormat;
+use jockey_cli::output::{format_output, parse_image, FileEntry, Repository};
+use jockey_cli::split::SplitSize;
+use jockey_cli::tokens::default_tokenizer;
+use jockey_cli::truncate::{truncate, FileLimits};
+
+fn numbered_lines(count: usize) -> String {
+    (1..=count).map(|i| format!("line {}\n", i)).collect()
+}
+
+#[test]
+fn test_line_limit_keeps_head_and_tail() {
+    let limits = FileLimits { max_size: None, max_lines: Some(6) };
+    let truncated = truncate(&numbered_lines(20_000), limits, default_tokenizer()).unwrap();
+
+    assert_eq!(
+        truncated,
+        "line 1\nline 2\nline 3\nline 4\n… 19,994 lines omitted …\nline 19999\nline 20000\n"
+    );
+    assert_eq!(truncate(&numbered_lines(6), limits, default_tokenizer()), None);
+}
+
+#[test]
+fn test_size_limit_keeps_lines_that_fit() {
+    let content = numbered_lines(1000);
+    let limits = FileLimits { max_size: Some(SplitSize::Bytes(200)), max_lines: None };
+    let truncated = truncate(&content, limits, default_tokenizer()).unwrap();
+
+    assert!(truncated.len() <= 200);
+    assert!(truncated.starts_with("line 1\nline 2\n"));
+    assert!(truncated.ends_with("line 999\nline 1000\n"));
+    assert!(truncated.contains(" lines omitted …\n"));
+    assert_eq!(truncate(&content, FileLimits::default(), default_tokenizer()), None);
+}
+
+#[test]
+fn test_truncated_flag_round_trips() {
+    let limits = FileLimits { max_size: None, max_lines: Some(3) };
+    let mut entry = FileEntry::new("schema.json", truncate(&numbered_lines(10), limits, default_tokenizer()).unwrap());
+    entry.truncated = true;
+    assert!(!entry.is_complete());
+
+    for format in [OutputFormat::Md, OutputFormat::Txt, OutputFormat::Json, OutputFormat::Xml] {
+        let image = format_output(Repository::new("project\n", vec![entry.clone()]), format).unwrap();
+        assert_eq!(parse_image(&image).unwrap().files, vec![entry.clone()]);
+    }
+}
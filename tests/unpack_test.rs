@@ -0,0 +1,140 @@
+use assert_fs::prelude::*;
+use jockey_cli::cli::{Commands, Config, OutputFormat};
+use jockey_cli::output::{format_output, parse_image, FileEntry, FileHistory, GitMetadata, Repository};
+use jockey_cli::process;
+use std::path::PathBuf;
+
+fn tricky_repo() -> Repository {
+    let files = [
+        ("src/main.rs", "fn main() {\n    println!(\"hi\");\n}\n"),
+        ("README.md", "# Title\n\n````rust\nlet fenced = \"```\";\n````\n"),
+        ("no_newline.txt", "last line without newline"),
+        ("windows.txt", "crlf line\r\nanother\r\n"),
+        ("empty.txt", ""),
+        ("blank_lines.txt", "\n\n\n"),
+        ("cdata.xml", "<![CDATA[ nested ]]> and </document>"),
+        ("headers.md", "## File: fake.rs\n\n```\nnot real\n```\nFile: fake (1 lines)\n"),
+    ];
+    let mut files = files
+        .iter()
+        .map(|(path, content)| FileEntry::new(*path, *content))
+        .collect::<Vec<_>>();
+    files[3].encoding = Some("windows-1252".to_string());
+    // Review images: diffs may hold separator-like lines and fences of their own
+    files[0].diff = Some("--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1 +1 @@\n--------- old\n+```new\n".to_string());
+    files[1].related = true;
+    files[2].history = Some(FileHistory {
+        commit: "1a2b3c4".to_string(),
+        date: "2024-05-01".to_string(),
+        subject: "Merge: fix <parser>; last pass on \"quotes\"".to_string(),
+        churn: 40,
+        window: "2 weeks".to_string(),
+    });
+    files[6].history = Some(FileHistory { churn: 1, ..files[2].history.clone().unwrap() });
+    let mut repo = Repository::new("project\n├── src\n│   └── main.rs\n└── README.md\n", files);
+    repo.git = Some(GitMetadata {
+        branch: Some("feature/review".to_string()),
+        commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+        subject: "Fix <xml> & \"quotes\" (#12)".to_string(),
+        remote: Some("https://github.com/owner/repo.git".to_string()),
+        dirty: Some(true),
+        modified: Some(3),
+    });
+    repo
+}
+
+#[test]
+fn test_round_trip_all_formats() {
+    let formats = [
+        OutputFormat::Md,
+        OutputFormat::Txt,
+        OutputFormat::Json,
+        OutputFormat::Yaml,
+        OutputFormat::Xml,
+    ];
+    for format in formats {
+        let label = format!("{:?}", format);
+        let image = format_output(tricky_repo(), format).unwrap();
+        let parsed = parse_image(&image).unwrap();
+        assert_eq!(parsed.files, tricky_repo().files, "{} round trip", label);
+        assert_eq!(parsed.tree.trim_end(), tricky_repo().tree.trim_end(), "{} tree", label);
+        assert_eq!(parsed.git, tricky_repo().git, "{} git metadata", label);
+    }
+}
+
+#[test]
+fn test_revision_metadata_has_no_working_tree_state() {
+    let mut repo = Repository::new("project\n", vec![FileEntry::new("main.rs", "fn main() {}\n")]);
+    repo.git = Some(GitMetadata {
+        commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+        subject: "Release 1.0".to_string(),
+        ..GitMetadata::default()
+    });
+    for format in [OutputFormat::Md, OutputFormat::Txt, OutputFormat::Json, OutputFormat::Yaml, OutputFormat::Xml] {
+        let label = format!("{:?}", format);
+        let image = format_output(repo.clone(), format).unwrap();
+        assert!(!image.contains("Working tree") && !image.contains("dirty"), "{}: {}", label, image);
+        assert_eq!(parse_image(&image).unwrap().git, repo.git, "{} git metadata", label);
+    }
+}
+
+#[test]
+fn test_absolute_paths_are_made_relative_to_tree_root() {
+    let repo = Repository::new(
+        "jockey-cli\n",
+        vec![
+            FileEntry::new("/Users/someone/Desktop/jockey-cli/src/lib.rs", ""),
+            FileEntry::new("/Users/someone/Desktop/jockey-cli/src/cli/mod.rs", ""),
+        ],
+    );
+    assert_eq!(
+        repo.relative_paths().unwrap(),
+        vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/cli/mod.rs")]
+    );
+
+    let escaping = Repository::new("", vec![FileEntry::new("../outside.txt", "")]);
+    assert!(escaping.relative_paths().is_err());
+}
+
+#[tokio::test]
+async fn test_unpack_recreates_files() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let image = temp.child("image.md");
+    image
+        .write_str(&format_output(tricky_repo(), OutputFormat::Md).unwrap())
+        .unwrap();
+    let target = temp.child("out");
+
+    let unpack = |force| Config {
+        command: Commands::Unpack {
+            image: image.path().to_path_buf(),
+            into: Some(target.path().to_path_buf()),
+            force,
+        },
+    };
+
+    process(unpack(false)).await.unwrap();
+    for file in tricky_repo().files {
+        target.child(&file.path).assert(file.content.as_str());
+    }
+
+    // A second run must not overwrite without --force
+    assert!(process(unpack(false)).await.is_err());
+    process(unpack(true)).await.unwrap();
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn test_outline_flag_survives_round_trip() {
+    let mut outline = FileEntry::new("src/big.rs", "pub fn big() {\n");
+    outline.outlined = true;
+    let repo = Repository::new("project\n", vec![outline, FileEntry::new("src/small.rs", "fn small() {}\n")]);
+
+    for format in [OutputFormat::Md, OutputFormat::Txt, OutputFormat::Json, OutputFormat::Yaml, OutputFormat::Xml] {
+        let parsed = parse_image(&format_output(repo.clone(), format).unwrap()).unwrap();
+        assert!(parsed.files[0].outlined);
+        assert!(!parsed.files[1].outlined);
+        assert_eq!(parsed.files[0].content, "pub fn big() {\n");
+    }
+}